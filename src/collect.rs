@@ -0,0 +1,1081 @@
+use crate::{Config, SysInfo};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    time::{Duration, Instant},
+};
+
+/// Where the host's /proc and /sys are read from, moved by --proc-root and
+/// --sys-root when they are bind-mounted into a container
+#[cfg(not(test))]
+static ROOTS: std::sync::RwLock<Roots> = std::sync::RwLock::new(Roots::new());
+
+// Unit tests run side by side on threads of their own, each pointing the
+// roots at its own fixture
+#[cfg(test)]
+thread_local! {
+    static ROOTS: std::cell::RefCell<Roots> = const { std::cell::RefCell::new(Roots::new()) };
+}
+
+struct Roots {
+    proc: String,
+    sys: String,
+}
+
+impl Roots {
+    const fn new() -> Self {
+        Self {
+            proc: String::new(),
+            sys: String::new(),
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn with_roots<T>(f: impl FnOnce(&mut Roots) -> T) -> T {
+    f(&mut ROOTS.write().unwrap())
+}
+
+#[cfg(test)]
+fn with_roots<T>(f: impl FnOnce(&mut Roots) -> T) -> T {
+    ROOTS.with_borrow_mut(f)
+}
+
+/// Why a metric couldn't be collected this time round
+#[derive(Debug)]
+pub enum CollectError {
+    /// A /proc or /sys file couldn't be read
+    Read(String, io::Error),
+    /// A file was read but didn't hold what we expected
+    Parse(String),
+    /// statvfs failed for a filesystem
+    Statvfs(String, io::Error),
+}
+
+impl fmt::Display for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollectError::Read(path, err) => write!(f, "unable to read {path}: {err}"),
+            CollectError::Parse(path) => write!(f, "unexpected contents in {path}"),
+            CollectError::Statvfs(path, err) => write!(f, "unable to statvfs {path}: {err}"),
+        }
+    }
+}
+
+pub fn set_roots(config: &Config) {
+    with_roots(|roots| {
+        roots.proc = config.proc_root.clone();
+        roots.sys = config.sys_root.clone();
+    });
+}
+
+/// Moves a /proc or /sys path under its configured root, paths are written
+/// as usual everywhere else
+pub(crate) fn host_path(path: &str) -> String {
+    with_roots(|roots| {
+        for (mount, root) in [("/proc/", &roots.proc), ("/sys/", &roots.sys)] {
+            if let Some(rest) = path.strip_prefix(mount)
+                && !root.is_empty()
+            {
+                return format!("{root}/{rest}");
+            }
+        }
+        path.to_string()
+    })
+}
+
+pub(crate) fn read_file(path: &str) -> Result<String, CollectError> {
+    let path = host_path(path);
+    fs::read_to_string(&path).map_err(|err| CollectError::Read(path, err))
+}
+
+/// A decimal from /proc. Rust's parse never looks at the locale, the
+/// kernel always writes `0.52`, but a comma is taken as the decimal point
+/// too in case something in between was localized. NaN and infinity are
+/// as malformed as anything else.
+pub(crate) fn parse_float(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let value = match value.contains('.') {
+        true => value.to_string(),
+        false => value.replacen(',', ".", 1),
+    };
+    value.parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+/// The /proc/meminfo fields we use, in KiB. Looked up by name so the order
+/// and presence of lines doesn't matter.
+#[derive(Default)]
+pub struct MemInfo {
+    pub(crate) total: u64,
+    pub(crate) available: Option<u64>,
+    pub(crate) free: u64,
+    pub(crate) buffers: u64,
+    pub(crate) cached: u64,
+    pub(crate) dirty: u64,
+    pub(crate) s_reclaimable: u64,
+    pub(crate) swap_total: u64,
+    pub(crate) swap_free: u64,
+}
+
+impl MemInfo {
+    pub fn read() -> Result<Self, CollectError> {
+        Ok(Self::parse(&read_file("/proc/meminfo")?))
+    }
+
+    pub(crate) fn parse(meminfo: &str) -> Self {
+        let mut info = Self::default();
+        for line in meminfo.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let Some(value) = value.split_whitespace().next().and_then(|v| v.parse().ok()) else {
+                continue;
+            };
+            match key {
+                "MemTotal" => info.total = value,
+                "MemAvailable" => info.available = Some(value),
+                "MemFree" => info.free = value,
+                "Buffers" => info.buffers = value,
+                "Cached" => info.cached = value,
+                "Dirty" => info.dirty = value,
+                "SReclaimable" => info.s_reclaimable = value,
+                "SwapTotal" => info.swap_total = value,
+                "SwapFree" => info.swap_free = value,
+                _ => {}
+            }
+        }
+        info
+    }
+
+    /// Kernels before 3.14 have no MemAvailable, so approximate it the way
+    /// free(1) used to
+    pub(crate) fn available(&self) -> u64 {
+        self.available
+            .unwrap_or(self.free + self.buffers + self.cached)
+    }
+}
+
+/// The metrics the memory breakdown is sent as, in the order
+/// [`SysInfo::mem_breakdown`] returns them
+pub(crate) const MEM_BREAKDOWN: [&str; 4] =
+    ["membuffers", "memcached", "memdirty", "memreclaimable"];
+
+/// A snapshot of the aggregate `cpu` line of /proc/stat, in jiffies, and
+/// of the scheduler counters that come with it in the same read
+#[derive(Clone, Default)]
+pub struct CpuTimes {
+    pub(crate) idle: u64,
+    /// Idle time with I/O outstanding, part of `idle`
+    pub(crate) iowait: u64,
+    /// Time a hypervisor ran something else while we wanted the CPU
+    pub(crate) steal: u64,
+    pub(crate) total: u64,
+    /// Context switches since boot
+    pub(crate) ctxt: u64,
+    /// Interrupts serviced since boot
+    pub(crate) intr: u64,
+    /// The `cpu<n>` lines by core number. A core taken offline drops out of
+    /// /proc/stat, so the numbers needn't be contiguous.
+    pub(crate) cores: BTreeMap<u32, CoreTimes>,
+}
+
+/// Idle and total jiffies of one `cpu<n>` line
+#[derive(Clone, Copy, Default)]
+pub(crate) struct CoreTimes {
+    idle: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    pub fn read() -> Result<Self, CollectError> {
+        Ok(Self::parse(&read_file("/proc/stat")?))
+    }
+
+    /// Idle includes iowait, which is kept apart as well. The total is user
+    /// through steal, guest time is already counted in user. Kernels before
+    /// 2.6.11 have no steal column, which leaves it 0.
+    pub(crate) fn parse(stat: &str) -> Self {
+        let fields = |line: &str| -> Vec<u64> {
+            line.split_whitespace()
+                .skip(1)
+                .take(8)
+                .map(|v| v.parse().unwrap_or(0))
+                .collect()
+        };
+        let idle = |fields: &[u64]| fields.get(3).unwrap_or(&0) + fields.get(4).unwrap_or(&0);
+        let cpu = fields(stat.lines().find(|l| l.starts_with("cpu ")).unwrap_or(""));
+        let cores = stat
+            .lines()
+            .filter_map(|line| {
+                let (name, _) = line.split_once(' ')?;
+                let core = name.strip_prefix("cpu")?.parse().ok()?;
+                let fields = fields(line);
+                Some((
+                    core,
+                    CoreTimes {
+                        idle: idle(&fields),
+                        total: fields.iter().sum(),
+                    },
+                ))
+            })
+            .collect();
+        // `intr` is followed by a count per interrupt line, the first
+        // number is their total
+        let counter = |name: &str| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix(name)?.split_whitespace().next())
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            idle: idle(&cpu),
+            iowait: *cpu.get(4).unwrap_or(&0),
+            steal: *cpu.get(7).unwrap_or(&0),
+            total: cpu.iter().sum(),
+            ctxt: counter("ctxt "),
+            intr: counter("intr "),
+            cores,
+        }
+    }
+
+    /// Percent of the time since `previous` that the CPU was busy
+    pub fn busy_since(&self, previous: &CpuTimes) -> f64 {
+        busy_percent(
+            self.idle.saturating_sub(previous.idle),
+            self.total.saturating_sub(previous.total),
+        )
+    }
+
+    /// Percent of the time since `previous` that each core was busy, by
+    /// core number. A core that wasn't in `previous`, brought online since,
+    /// has nothing to compare with and is left out until the next sample.
+    pub fn cores_busy_since(&self, previous: &CpuTimes) -> Vec<(u32, f64)> {
+        self.cores
+            .iter()
+            .filter_map(|(core, times)| {
+                let before = previous.cores.get(core)?;
+                let busy = busy_percent(
+                    times.idle.saturating_sub(before.idle),
+                    times.total.saturating_sub(before.total),
+                );
+                Some((*core, busy))
+            })
+            .collect()
+    }
+
+    /// Context switches and interrupts since `previous`, 0 for a counter
+    /// that went backwards
+    pub fn switches_since(&self, previous: &CpuTimes) -> (u64, u64) {
+        (
+            self.ctxt.saturating_sub(previous.ctxt),
+            self.intr.saturating_sub(previous.intr),
+        )
+    }
+
+    /// Percent of the time since `previous` that was stolen by the
+    /// hypervisor, counted in busy as well
+    pub fn steal_since(&self, previous: &CpuTimes) -> f64 {
+        self.share_since(previous, |times| times.steal)
+    }
+
+    /// Percent of the time since `previous` spent idle waiting on I/O,
+    /// counted in idle as well
+    pub fn iowait_since(&self, previous: &CpuTimes) -> f64 {
+        self.share_since(previous, |times| times.iowait)
+    }
+
+    /// Percent of the jiffies since `previous` that `column` grew by
+    fn share_since(&self, previous: &CpuTimes, column: fn(&CpuTimes) -> u64) -> f64 {
+        let total = self.total.saturating_sub(previous.total);
+        if total == 0 {
+            return 0f64;
+        }
+        column(self).saturating_sub(column(previous)) as f64 / total as f64 * 100.0
+    }
+}
+
+/// The busy share of `total` jiffies, 0 when no time has passed
+fn busy_percent(idle: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0f64;
+    }
+    (1.0 - idle as f64 / total as f64) * 100.0
+}
+
+/// /proc/loadavg, which also counts the kernel's scheduling entities so the
+/// process count comes without scanning /proc
+#[derive(Clone, Copy, Default)]
+pub struct LoadAvg {
+    /// 1, 5 and 15 minute load averages as the kernel reports them
+    pub(crate) load: [f32; 3],
+    /// Processes and threads runnable right now
+    pub(crate) running: u64,
+    /// Every process and thread
+    pub(crate) total: u64,
+}
+
+impl LoadAvg {
+    pub fn read() -> Result<Self, CollectError> {
+        Self::parse(&read_file("/proc/loadavg")?)
+            .ok_or(CollectError::Parse("/proc/loadavg".to_string()))
+    }
+
+    /// `0.52 0.58 0.59 2/1043 12345`, the three averages, running/total
+    /// and the last PID handed out. Without the averages it's `None`, which
+    /// the load collector warns about and keeps its last value for. A
+    /// missing or malformed running/total counts 0 of each.
+    pub(crate) fn parse(contents: &str) -> Option<Self> {
+        let mut fields = contents.split_whitespace();
+        let mut load = [0f32; 3];
+        for average in &mut load {
+            *average = parse_float(fields.next()?)? as f32;
+        }
+        let (running, total) = fields
+            .next()
+            .and_then(|tasks| tasks.split_once('/'))
+            .and_then(|(running, total)| Some((running.parse().ok()?, total.parse().ok()?)))
+            .unwrap_or((0, 0));
+        Some(Self {
+            load,
+            running,
+            total,
+        })
+    }
+
+    pub fn running(&self) -> u64 {
+        self.running
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// The TCP counters of /proc/net/snmp we use, each counting since boot
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpStats {
+    pub(crate) active_opens: u64,
+    pub(crate) in_segs: u64,
+    pub(crate) out_segs: u64,
+    pub(crate) retrans_segs: u64,
+}
+
+impl TcpStats {
+    pub fn read() -> Result<Self, CollectError> {
+        Self::parse(&read_file("/proc/net/snmp")?)
+            .ok_or(CollectError::Parse("/proc/net/snmp".to_string()))
+    }
+
+    /// Each protocol has two `Tcp:` lines, the field names and then their
+    /// values, so fields are found by name rather than position
+    pub(crate) fn parse(snmp: &str) -> Option<Self> {
+        let mut lines = snmp.lines().filter(|line| line.starts_with("Tcp:"));
+        let names = lines.next()?.split_whitespace();
+        let values = lines.next()?.split_whitespace();
+        let mut stats = Self::default();
+        let mut found = 0;
+        for (name, value) in names.zip(values).skip(1) {
+            let field = match name {
+                "ActiveOpens" => &mut stats.active_opens,
+                "InSegs" => &mut stats.in_segs,
+                "OutSegs" => &mut stats.out_segs,
+                "RetransSegs" => &mut stats.retrans_segs,
+                _ => continue,
+            };
+            *field = value.parse().ok()?;
+            found += 1;
+        }
+        (found == 4).then_some(stats)
+    }
+
+    /// Segments retransmitted since `previous`, 0 if the counter went
+    /// backwards
+    pub fn retransmits_since(&self, previous: &TcpStats) -> u64 {
+        self.retrans_segs.saturating_sub(previous.retrans_segs)
+    }
+}
+
+/// /proc/sys/fs/file-nr, the system-wide file handle counts
+#[derive(Clone, Copy, Default)]
+pub struct FileNr {
+    pub(crate) allocated: u64,
+    /// Allocated but unused, always 0 since Linux 2.6
+    pub(crate) free: u64,
+    /// fs.file-max
+    pub(crate) max: u64,
+}
+
+impl FileNr {
+    pub fn read() -> Result<Self, CollectError> {
+        Self::parse(&read_file("/proc/sys/fs/file-nr")?)
+            .ok_or(CollectError::Parse("/proc/sys/fs/file-nr".to_string()))
+    }
+
+    /// Three tab separated columns, `allocated free max`
+    pub(crate) fn parse(contents: &str) -> Option<Self> {
+        let mut fields = contents.split_whitespace().map(|field| field.parse().ok());
+        let file_nr = Self {
+            allocated: fields.next()??,
+            free: fields.next()??,
+            max: fields.next()??,
+        };
+        fields.next().is_none().then_some(file_nr)
+    }
+
+    /// File handles in use
+    pub fn used(&self) -> u64 {
+        self.allocated.saturating_sub(self.free)
+    }
+
+    /// Percent of fs.file-max in use
+    pub fn used_percent(&self) -> f64 {
+        if self.max == 0 {
+            return 0f64;
+        }
+        self.used() as f64 * 100.0 / self.max as f64
+    }
+}
+
+/// One line of /proc/mounts
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mount {
+    pub device: String,
+    pub path: String,
+    pub fs_type: String,
+}
+
+impl Mount {
+    pub fn read() -> Result<Vec<Self>, CollectError> {
+        Ok(Self::parse(&read_file("/proc/mounts")?))
+    }
+
+    /// `device path type options dump pass`, with spaces and the like in
+    /// paths written as octal escapes
+    pub(crate) fn parse(mounts: &str) -> Vec<Self> {
+        mounts
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(Self {
+                    device: unescape(fields.next()?),
+                    path: unescape(fields.next()?),
+                    fs_type: fields.next()?.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Filesystem types `auto` skips: kernel interfaces, memory-backed and
+/// read-only image filesystems whose free space means nothing
+pub(crate) const PSEUDO_FS_TYPES: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "overlay",
+    "proc",
+    "pstore",
+    "ramfs",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "squashfs",
+    "sysfs",
+    "tmpfs",
+    "tracefs",
+];
+
+impl Mount {
+    /// The mount points of real filesystems, each once, for `--filesystem
+    /// auto`. A type in `include` is kept even if it's a pseudo filesystem,
+    /// one in `exclude` is skipped even if it isn't.
+    pub(crate) fn discover(
+        mounts: &[Mount],
+        include: &[String],
+        exclude: &[String],
+    ) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+        for mount in mounts {
+            let real = if include.contains(&mount.fs_type) {
+                true
+            } else {
+                !exclude.contains(&mount.fs_type)
+                    && !PSEUDO_FS_TYPES.contains(&mount.fs_type.as_str())
+            };
+            if real && !paths.contains(&mount.path) {
+                paths.push(mount.path.clone());
+            }
+        }
+        paths
+    }
+}
+
+/// Undoes the `\040` style escapes the kernel writes mount fields with
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let raw = field.as_bytes();
+    let mut i = 0;
+    while i < raw.len() {
+        let octal = raw.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (raw[i], octal) {
+            (b'\\', Some(byte)) => {
+                bytes.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Whether an unreadable interface should be warned about again, at most
+/// once a minute so one that stays missing doesn't flood the log
+fn warning_due(last_warning: &mut Option<Instant>) -> bool {
+    let due = last_warning.is_none_or(|last| last.elapsed() >= Duration::from_secs(60));
+    if due {
+        *last_warning = Some(Instant::now());
+    }
+    due
+}
+
+/// Byte counters for one network interface
+pub(crate) struct Interface {
+    pub(crate) name: String,
+    /// Appended to net-rx/net-tx, empty when only one interface is watched
+    /// so the metric names stay as they always were
+    pub(crate) suffix: String,
+    pub(crate) last_seen_rx: u64,
+    pub(crate) last_seen_tx: u64,
+    pub(crate) rx: u64,
+    pub(crate) tx: u64,
+    /// When it was last warned about being unreadable
+    pub(crate) last_warning: Option<Instant>,
+}
+
+impl Interface {
+    pub(crate) fn new(name: &str, suffix: String) -> Self {
+        Self {
+            name: name.to_string(),
+            suffix,
+            last_seen_rx: SysInfo::initial("net-rx", SysInfo::net_stats(name, "r")),
+            last_seen_tx: SysInfo::initial("net-tx", SysInfo::net_stats(name, "t")),
+            rx: 0u64,
+            tx: 0u64,
+            last_warning: None,
+        }
+    }
+
+    /// Counters are reported as the delta since the previous refresh, so
+    /// they are bytes per interval whatever the interval is. A counter that
+    /// went backwards (link reset, driver rollover) reports 0 for that
+    /// interval rather than underflowing.
+    ///
+    /// An interface that can't be read (unplugged, tunnel down) reports 0
+    /// and keeps its old baseline for when it comes back.
+    pub(crate) fn refresh(&mut self) {
+        match (
+            SysInfo::net_stats(&self.name, "r"),
+            SysInfo::net_stats(&self.name, "t"),
+        ) {
+            (Ok(new_rx), Ok(new_tx)) => {
+                self.rx = new_rx.saturating_sub(self.last_seen_rx);
+                self.tx = new_tx.saturating_sub(self.last_seen_tx);
+                self.last_seen_rx = new_rx;
+                self.last_seen_tx = new_tx;
+            }
+            (Err(err), _) | (_, Err(err)) => {
+                self.rx = 0;
+                self.tx = 0;
+                if warning_due(&mut self.last_warning) {
+                    warn!("Reporting 0 for network interface {}: {err}", self.name);
+                }
+            }
+        }
+    }
+}
+
+/// Error and drop counters of one network interface, in [`NET_ERRORS`] order
+pub(crate) struct InterfaceErrors {
+    pub(crate) name: String,
+    /// Named like [`Interface::suffix`]
+    pub(crate) suffix: String,
+    pub(crate) last_seen: [u64; 4],
+    pub(crate) errors: [u64; 4],
+    pub(crate) last_warning: Option<Instant>,
+}
+
+/// The statistics files the error counters are read from, and the metrics
+/// they're sent as
+pub(crate) const NET_ERRORS: [(&str, &str); 4] = [
+    ("rx_errors", "net-rx-err"),
+    ("tx_errors", "net-tx-err"),
+    ("rx_dropped", "net-rx-drop"),
+    ("tx_dropped", "net-tx-drop"),
+];
+
+impl InterfaceErrors {
+    pub(crate) fn new(name: &str, suffix: String) -> Self {
+        Self {
+            name: name.to_string(),
+            suffix,
+            last_seen: SysInfo::initial("net-errors", SysInfo::net_errors(name)),
+            errors: [0; 4],
+            last_warning: None,
+        }
+    }
+
+    /// Counts per interval, handled like the byte counters
+    pub(crate) fn refresh(&mut self) {
+        match SysInfo::net_errors(&self.name) {
+            Ok(counters) => {
+                self.errors = SysInfo::deltas(counters, self.last_seen);
+                self.last_seen = counters;
+            }
+            Err(err) => {
+                self.errors = [0; 4];
+                if warning_due(&mut self.last_warning) {
+                    warn!(
+                        "Reporting 0 errors for network interface {}: {err}",
+                        self.name
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Read/write throughput of one block device
+pub(crate) struct Disk {
+    pub(crate) name: String,
+    /// Bytes, from the sector counters in /proc/diskstats
+    pub(crate) last_seen_read: u64,
+    pub(crate) last_seen_written: u64,
+    pub(crate) read: u64,
+    pub(crate) written: u64,
+}
+
+impl Disk {
+    pub(crate) fn new(name: &str) -> Self {
+        let (last_seen_read, last_seen_written) =
+            SysInfo::initial("disk-read", SysInfo::disk_io(name));
+        Self {
+            name: name.to_string(),
+            last_seen_read,
+            last_seen_written,
+            read: 0u64,
+            written: 0u64,
+        }
+    }
+
+    /// Bytes per interval, handled like the network counters: the first
+    /// sample is 0, a counter that went backwards reports 0, and a device
+    /// that can't be read reports 0 and keeps its baseline.
+    pub(crate) fn refresh(&mut self) {
+        match SysInfo::disk_io(&self.name) {
+            Ok((new_read, new_written)) => {
+                self.read = new_read.saturating_sub(self.last_seen_read);
+                self.written = new_written.saturating_sub(self.last_seen_written);
+                self.last_seen_read = new_read;
+                self.last_seen_written = new_written;
+            }
+            Err(err) => {
+                self.read = 0;
+                self.written = 0;
+                SysInfo::collect_failed("disk-read", err);
+            }
+        }
+    }
+}
+
+/// The statvfs(3) counts we use, widened to u64. libc's field types differ
+/// between glibc and musl and between 32 and 64 bit, so nothing past
+/// [`SysInfo::statvfs`] sees them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsStats {
+    /// Size in fragments
+    pub blocks: u64,
+    /// Fragments free to unprivileged users
+    pub blocks_available: u64,
+    pub fragment_size: u64,
+    pub files: u64,
+    /// Inodes free to unprivileged users
+    pub files_available: u64,
+    /// The mount flags, `ST_RDONLY` and the rest
+    pub flags: u64,
+}
+
+/// Free space on one monitored filesystem
+pub(crate) struct Filesystem {
+    pub(crate) path: String,
+    /// Appended to diskfree, empty when only one filesystem is watched
+    pub(crate) suffix: String,
+    pub(crate) disk_free: f64,
+    pub(crate) free_bytes: u64,
+    pub(crate) inode_free: f64,
+    pub(crate) read_only: bool,
+}
+
+impl Filesystem {
+    pub(crate) fn new(path: &str, suffix: String) -> Self {
+        let mut filesystem = Self {
+            path: path.to_string(),
+            suffix,
+            disk_free: 0f64,
+            free_bytes: 0u64,
+            inode_free: 0f64,
+            read_only: false,
+        };
+        filesystem.refresh();
+        filesystem
+    }
+
+    /// Every metric comes from the one statvfs call, and keeps its last
+    /// value when it fails
+    pub(crate) fn refresh(&mut self) {
+        match SysInfo::statvfs(&self.path) {
+            Ok(stat) => {
+                self.disk_free = SysInfo::disk_free(&self.path, &stat);
+                self.free_bytes = SysInfo::disk_free_bytes(&stat);
+                self.inode_free = SysInfo::inode_free(&stat);
+                self.read_only = SysInfo::read_only(&stat);
+            }
+            Err(err) => SysInfo::collect_failed("diskfree", err),
+        }
+    }
+
+    /// Slashes and dots would nest the metric, so the path becomes a single
+    /// component: / is root, /var/lib is var-lib
+    pub(crate) fn metric_name(path: &str) -> String {
+        match path.trim_matches('/') {
+            "" => "root".to_string(),
+            path => path
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+                    _ => '-',
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn roots() {
+        assert_eq!(host_path("/proc/meminfo"), "/proc/meminfo");
+        let proc = TempDir::new("proc-root");
+        let sys = TempDir::new("sys-root");
+        proc.write("meminfo", "MemTotal: 1000 kB\nMemAvailable: 400 kB\n");
+        proc.write("sys/kernel/hostname", "host-box\n");
+        sys.write("class/net/eth0/operstate", "up\n");
+        // A trailing slash is dropped rather than doubled
+        let proc_root = format!("{}/", proc.path().display());
+        crate::set_roots(&testing::config(&[
+            "--proc-root",
+            &proc_root,
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]));
+        assert_eq!(host_path("/proc/meminfo"), proc.join("meminfo"));
+        assert_eq!(host_path("/sys/class/net"), sys.join("class/net"));
+        // Only /proc and /sys move
+        assert_eq!(host_path("/etc/machine-id"), "/etc/machine-id");
+        assert_eq!(host_path("/process"), "/process");
+        assert_eq!(MemInfo::read().unwrap().available(), 400);
+        assert_eq!(SysInfo::get_hostname().unwrap(), "host-box");
+        assert_eq!(SysInfo::operstate("eth0").unwrap(), "up");
+    }
+
+    #[test]
+    fn counter_reset() {
+        let sys = TempDir::new("counter-reset");
+        testing::sys_root(&sys);
+        testing::counters(&sys, "eth0", 5000, 3000);
+        let mut interface = Interface::new("eth0", String::new());
+        testing::counters(&sys, "eth0", 6000, 3500);
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (1000, 500));
+        // The driver was reloaded and counts from 0 again
+        testing::counters(&sys, "eth0", 200, 100);
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (0, 0));
+        testing::counters(&sys, "eth0", 700, 400);
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (500, 300));
+    }
+
+    #[test]
+    fn missing_interface() {
+        let sys = TempDir::new("missing-interface");
+        testing::sys_root(&sys);
+        let mut interface = Interface::new("usb0", String::new());
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (0, 0));
+        // With no baseline taken, its first interval counts from 0
+        testing::counters(&sys, "usb0", 4000, 2000);
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (4000, 2000));
+        fs::remove_dir_all(sys.path().join("class/net/usb0")).unwrap();
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (0, 0));
+        assert_eq!(
+            (interface.last_seen_rx, interface.last_seen_tx),
+            (4000, 2000)
+        );
+        testing::counters(&sys, "usb0", 4100, 2050);
+        interface.refresh();
+        assert_eq!((interface.rx, interface.tx), (100, 50));
+    }
+
+    #[test]
+    fn missing_interface_warnings() {
+        let sys = TempDir::new("missing-warnings");
+        testing::sys_root(&sys);
+        // Each missing interface gets its own warning, then one a minute
+        let mut usb0 = Interface::new("usb0", String::new());
+        let mut usb1 = InterfaceErrors::new("usb1", String::new());
+        usb0.refresh();
+        usb1.refresh();
+        let warned = usb0.last_warning.unwrap();
+        assert!(usb1.last_warning.is_some());
+        usb0.refresh();
+        assert_eq!(usb0.last_warning, Some(warned));
+        assert!(!warning_due(&mut usb0.last_warning));
+        let mut minute_ago = Instant::now().checked_sub(Duration::from_secs(60));
+        assert!(warning_due(&mut minute_ago));
+    }
+
+    #[test]
+    fn disk_io() {
+        let proc = TempDir::new("diskstats");
+        let diskstats = |read: u64, written: u64| {
+            let line = |device: &str| {
+                format!(
+                    "   8       0 {device} 5000 100 {read} 800 3000 200 {written} 900 0 1000 1700\n"
+                )
+            };
+            proc.write(
+                "diskstats",
+                &(line("sda") + &line("sda1") + &line("nvme0n1")),
+            );
+        };
+        diskstats(1000, 2000);
+        crate::set_roots(&testing::config(&[
+            "--proc-root",
+            proc.path().to_str().unwrap(),
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]));
+        let mut disk = Disk::new("sda");
+        assert_eq!((disk.read, disk.written), (0, 0));
+        // Sectors are 512 bytes
+        diskstats(1010, 2100);
+        disk.refresh();
+        assert_eq!((disk.read, disk.written), (10 * 512, 100 * 512));
+        diskstats(1010, 2100);
+        disk.refresh();
+        assert_eq!((disk.read, disk.written), (0, 0));
+        let mut missing = Disk::new("sdb");
+        missing.refresh();
+        assert_eq!((missing.read, missing.written), (0, 0));
+    }
+
+    #[test]
+    fn mem_info_parse() {
+        let meminfo = MemInfo::parse("MemTotal: 8000000 kB\nMemAvailable: 2000000 kB\n");
+        assert_eq!(SysInfo::avail_mem(&meminfo), 25.0);
+        assert_eq!(SysInfo::avail_mem_bytes(&meminfo), 2_048_000_000);
+        // Shuffled, with lines we don't read and lines we can't
+        let meminfo = MemInfo::parse(
+            "Cached: 300 kB\n\
+             HugePages_Total: 0\n\
+             MemFree: 100 kB\n\
+             garbage\n\
+             MemTotal: 1000 kB\n\
+             Buffers: 200 kB\n",
+        );
+        assert_eq!((meminfo.total, meminfo.available), (1000, None));
+        assert_eq!(meminfo.available(), 600);
+        // MemAvailable wins over the approximation when it's there
+        let meminfo = MemInfo::parse("MemFree: 100 kB\nMemAvailable: 50 kB\n");
+        assert_eq!(meminfo.available(), 50);
+    }
+
+    #[test]
+    fn cpu_busy() {
+        let before =
+            CpuTimes::parse("cpu  4705 356 584 3699 23 0 0\ncpu0 4705 356 584 3699 23 0 0\n");
+        let after =
+            CpuTimes::parse("cpu  4905 356 634 3949 23 0 0\ncpu0 4905 356 634 3949 23 0 0\n");
+        // 250 of 500 jiffies busy
+        assert_eq!(after.busy_since(&before), 50.0);
+        // No time passed, nothing to divide by
+        assert_eq!(after.busy_since(&after), 0.0);
+        // Idle counts iowait too
+        let waiting = CpuTimes::parse("cpu  4905 356 634 3949 123 0 0\n");
+        assert_eq!(waiting.busy_since(&after), 0.0);
+        assert_eq!(CpuTimes::parse("").busy_since(&CpuTimes::default()), 0.0);
+    }
+
+    #[test]
+    fn cpu_steal() {
+        let before = CpuTimes::parse("cpu  4705 356 584 3699 23 23 0 120 0 0\n");
+        let after = CpuTimes::parse("cpu  4805 356 634 3749 23 23 0 170 0 0\n");
+        assert_eq!(after.steal_since(&before), 20.0);
+        assert_eq!(after.busy_since(&before), 80.0);
+        let old = CpuTimes::parse("cpu  4705 356 584 3699 23 23 0\n");
+        assert_eq!(old.steal_since(&CpuTimes::default()), 0.0);
+    }
+
+    #[test]
+    fn cores_busy() {
+        let before = CpuTimes::parse("cpu  300 0 100 600\ncpu0 100 0 50 350\ncpu1 200 0 50 250\n");
+        let after = CpuTimes::parse(
+            "cpu  455 0 100 850\ncpu0 150 0 50 500\ncpu1 300 0 50 350\ncpu2 5 0 0 0\n",
+        );
+        assert_eq!(after.cores_busy_since(&before), [(0, 25.0), (1, 50.0)]);
+        // cpu1 went offline
+        let later = CpuTimes::parse("cpu  510 0 100 1000\ncpu0 250 0 50 600\ncpu2 5 0 0 0\n");
+        assert_eq!(later.cores_busy_since(&after), [(0, 50.0), (2, 0.0)]);
+    }
+
+    #[test]
+    fn switches() {
+        let before = CpuTimes::parse("cpu  4705 356 584 3699\nintr 1462898 9 0 17\nctxt 5241184\n");
+        let after = CpuTimes::parse("cpu  4805 356 634 3749\nintr 1470123 9 0 25\nctxt 5263410\n");
+        assert_eq!(after.switches_since(&before), (22226, 7225));
+        assert_eq!(before.switches_since(&after), (0, 0));
+    }
+
+    #[test]
+    fn iowait() {
+        let before = CpuTimes::parse("cpu  100 0 100 700 100 0 0 0 0 0\n");
+        let after = CpuTimes::parse("cpu  150 0 150 900 200 0 0 0 0 0\n");
+        assert_eq!(after.iowait_since(&before), 25.0);
+        assert_eq!(after.busy_since(&before), 25.0);
+        assert_eq!(after.iowait_since(&after), 0.0);
+    }
+
+    #[test]
+    fn load_avg_parse() {
+        let load_avg = LoadAvg::parse("0.52 0.58 0.59 2/1043 12345\n").unwrap();
+        assert_eq!(load_avg.load, [0.52, 0.58, 0.59]);
+        assert_eq!((load_avg.running(), load_avg.total()), (2, 1043));
+        let load_avg = LoadAvg::parse("0,52 0,58 0,59 2/1043 12345\n").unwrap();
+        assert_eq!((load_avg.running(), load_avg.total()), (2, 1043));
+        for partial in [
+            "0.52 0.58 0.59",
+            "0.52 0.58 0.59 2-1043",
+            "0.52 0.58 0.59 2/x",
+            "0.52 0.58 0.59 /",
+        ] {
+            let load_avg = LoadAvg::parse(partial).unwrap();
+            assert_eq!(
+                (load_avg.running(), load_avg.total()),
+                (0, 0),
+                "{partial:?}"
+            );
+        }
+        for malformed in [
+            "",
+            "\n",
+            "0.52 0.58",
+            "a b c 2/1043",
+            "0.52 0.58 NaN 2/1043",
+            "\u{fffd}\u{fffd} 1 1 1/1",
+        ] {
+            assert!(LoadAvg::parse(malformed).is_none(), "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn tcp_stats_parse() {
+        let snmp = "\
+Ip: Forwarding DefaultTTL InReceives
+Ip: 1 64 183424
+Tcp: RtoAlgorithm RtoMin RtoMax MaxConn ActiveOpens PassiveOpens AttemptFails EstabResets CurrEstab InSegs OutSegs RetransSegs InErrs OutRsts InCsumErrors
+Tcp: 1 200 120000 -1 3517 412 61 98 14 1734211 1920097 1187 3 2208 0
+Udp: InDatagrams NoPorts InErrors OutDatagrams
+Udp: 20445 12 0 20516
+";
+        let stats = TcpStats::parse(snmp).unwrap();
+        assert_eq!(
+            (stats.active_opens, stats.in_segs, stats.out_segs),
+            (3517, 1734211, 1920097)
+        );
+        assert_eq!(stats.retransmits_since(&TcpStats::default()), 1187);
+        assert!(TcpStats::parse("Ip: Forwarding\nIp: 1\n").is_none());
+    }
+
+    #[test]
+    fn file_nr_parse() {
+        let file_nr = FileNr::parse("12384\t0\t1048576\n").unwrap();
+        assert_eq!(file_nr.used(), 12384);
+        assert_eq!(file_nr.used_percent(), 12384.0 * 100.0 / 1048576.0);
+        assert!(FileNr::parse("12384\t0\n").is_none());
+    }
+
+    #[test]
+    fn mount_parse() {
+        let mounts = Mount::parse(
+            "proc /proc proc rw,relatime 0 0\n/dev/sdb1 /mnt/usb\\040stick vfat rw 0 0\n",
+        );
+        assert_eq!(mounts[0].fs_type, "proc");
+        assert_eq!(mounts[1].path, "/mnt/usb stick");
+    }
+
+    #[test]
+    fn mount_discover() {
+        let mounts = Mount::parse(
+            "sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+udev /dev devtmpfs rw,nosuid,relatime,size=8145124k,mode=755 0 0
+devpts /dev/pts devpts rw,nosuid,noexec,relatime,gid=5,mode=620 0 0
+tmpfs /run tmpfs rw,nosuid,nodev,noexec,relatime,size=1634764k,mode=755 0 0
+/dev/nvme0n1p2 / ext4 rw,relatime,errors=remount-ro 0 0
+cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime 0 0
+/dev/loop0 /snap/core22/1380 squashfs ro,nodev,relatime 0 0
+/dev/nvme0n1p1 /boot/efi vfat rw,relatime,fmask=0077,dmask=0077 0 0
+/dev/mapper/data /var/lib xfs rw,relatime,attr2,inode64 0 0
+overlay /var/lib/docker/overlay2/3f9c/merged overlay rw,relatime 0 0
+/dev/sdb1 /srv btrfs rw,relatime,space_cache=v2 0 0
+tmpfs /run/user/1000 tmpfs rw,nosuid,nodev,relatime,size=1634760k 0 0
+",
+        );
+        assert_eq!(
+            Mount::discover(&mounts, &[], &[]),
+            ["/", "/boot/efi", "/var/lib", "/srv"]
+        );
+        assert_eq!(
+            Mount::discover(&mounts, &["tmpfs".to_string()], &["vfat".to_string()]),
+            ["/run", "/", "/var/lib", "/srv", "/run/user/1000"]
+        );
+    }
+}
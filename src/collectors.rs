@@ -0,0 +1,971 @@
+use crate::{
+    Config, CpuTimes, FileNr, LoadAvg, MemInfo, Metrics, SysInfo, TcpStats,
+    collect::{Disk, Filesystem, Interface, InterfaceErrors},
+    metrics::{
+        DiskMetrics, FilesystemMetrics, InterfaceErrorMetrics, InterfaceMetrics, LinkMetrics,
+    },
+};
+use std::mem;
+
+/// Every collector by the name `--disable` knows it as, and the metrics it
+/// reports
+pub(crate) const COLLECTORS: &[(&str, &[&str])] = &[
+    ("net", &["net-rx", "net-tx"]),
+    (
+        "net-errors",
+        &["net-rx-err", "net-tx-err", "net-rx-drop", "net-tx-drop"],
+    ),
+    ("link", &["net-up", "net-speed"]),
+    ("tcp", &["tcp-retrans"]),
+    ("conntrack", &["conntrack", "conntrack-pct"]),
+    ("uptime", &["uptime"]),
+    ("boottime", &["boottime"]),
+    ("availmem", &["availmem", "memused"]),
+    (
+        "membreakdown",
+        &["membuffers", "memcached", "memdirty", "memreclaimable"],
+    ),
+    ("swapused", &["swapused"]),
+    (
+        "diskfree",
+        &["diskfree", "diskused", "inodefree", "fs-readonly"],
+    ),
+    ("disk-io", &["disk-read", "disk-write"]),
+    ("load", &["load", "load5", "load15"]),
+    ("procs", &["procs", "procs-running", "procs-total"]),
+    ("zombies", &["zombies"]),
+    ("entropy", &["entropy"]),
+    ("kernel", &["kernel"]),
+    ("fd", &["fd-used", "fd-used-pct"]),
+    ("cpu", &["cpu", "cpusteal", "iowait", "ctxt", "intr"]),
+    ("cputemp", &["cputemp"]),
+    ("disktemp", &["disktemp"]),
+    ("psi", &["psi-cpu", "psi-mem", "psi-io"]),
+];
+
+/// Collectors that are off unless `--enable` names them, the ones too costly
+/// to run everywhere
+pub(crate) const OPT_IN: &[&str] = &["zombies"];
+
+/// One source of metrics. Each keeps its own last values, so one that
+/// can't be read this time round doesn't hold up the others. Adding a
+/// metric means an impl of this and a name in [`COLLECTORS`].
+pub(crate) trait Collector {
+    fn name(&self) -> &'static str;
+
+    /// Reads the source again
+    fn collect(&mut self);
+
+    /// Fills in this collector's part of a sample with its latest values
+    fn report(&self, metrics: &mut Metrics);
+
+    /// Applies a reloaded configuration, most collectors don't use it
+    fn reload(&mut self, _config: &Config) {}
+}
+
+/// The collectors `config` doesn't disable, in [`COLLECTORS`] order. One
+/// whose metrics are all left out by `--metrics` or `--disable-metrics`
+/// isn't run either. Collectors that are still enabled keep their state, so
+/// counters carry on across a reload.
+pub(crate) fn collectors(
+    config: &Config,
+    mut previous: Vec<Box<dyn Collector>>,
+) -> Vec<Box<dyn Collector>> {
+    COLLECTORS
+        .iter()
+        .filter(|(name, metrics)| {
+            !config.disabled.iter().any(|disabled| disabled == name)
+                && metrics
+                    .iter()
+                    .any(|metric| config.metrics.iter().any(|enabled| enabled == metric))
+        })
+        .map(
+            |&(name, _)| match previous.iter().position(|old| old.name() == name) {
+                Some(i) => {
+                    let mut collector = previous.swap_remove(i);
+                    collector.reload(config);
+                    collector
+                }
+                None => new(name, config),
+            },
+        )
+        .collect()
+}
+
+fn new(name: &str, config: &Config) -> Box<dyn Collector> {
+    match name {
+        "net" => Box::new(Net {
+            interfaces: SysInfo::interfaces(&config.interfaces),
+            all: config.all_interfaces,
+        }),
+        "net-errors" => Box::new(NetErrors(SysInfo::interface_errors(&config.interfaces))),
+        "link" => {
+            let mut link = Link(Vec::new());
+            link.reload(config);
+            link.collect();
+            Box::new(link)
+        }
+        "tcp" => Box::new(Tcp(TcpStats::read()
+            .inspect_err(|err| info!("Not reporting tcp-retrans: {err}"))
+            .ok()
+            .map(|last_seen| (last_seen, 0)))),
+        "conntrack" => Box::new(Conntrack(
+            SysInfo::conntrack()
+                .inspect_err(|err| info!("Not reporting conntrack: {err}"))
+                .ok(),
+        )),
+        "uptime" => Box::new(Uptime(SysInfo::initial("uptime", SysInfo::uptime()))),
+        "boottime" => Box::new(BootTime(SysInfo::initial("boottime", SysInfo::boot_time()))),
+        "availmem" => {
+            let meminfo = SysInfo::initial("availmem", MemInfo::read());
+            Box::new(AvailMem {
+                percent: SysInfo::avail_mem(&meminfo),
+                bytes: SysInfo::avail_mem_bytes(&meminfo),
+            })
+        }
+        "membreakdown" => {
+            let meminfo = SysInfo::initial("membreakdown", MemInfo::read());
+            Box::new(MemBreakdown {
+                percent: SysInfo::mem_breakdown_percent(&meminfo),
+                bytes: SysInfo::mem_breakdown(&meminfo),
+            })
+        }
+        "swapused" => Box::new(SwapUsed(SysInfo::swap_used(&SysInfo::initial(
+            "swapused",
+            MemInfo::read(),
+        )))),
+        "diskfree" => Box::new(DiskFree(SysInfo::filesystems(&SysInfo::filesystem_paths(
+            config,
+        )))),
+        "disk-io" => Box::new(DiskIo(SysInfo::disks(&config.disks))),
+        "load" => {
+            let cores = SysInfo::cores();
+            let load_avg = SysInfo::initial("load", LoadAvg::read());
+            Box::new(Load {
+                cores,
+                load_avg: load_avg.load,
+                load: SysInfo::load(load_avg.load, cores),
+            })
+        }
+        "procs" => Box::new(Procs(SysInfo::initial("procs", LoadAvg::read()))),
+        "zombies" => Box::new(Zombies(SysInfo::initial("zombies", SysInfo::zombies()))),
+        "entropy" => Box::new(Entropy(SysInfo::initial("entropy", SysInfo::entropy()))),
+        "kernel" => Box::new(Kernel(SysInfo::initial(
+            "kernel",
+            SysInfo::kernel_release(),
+        ))),
+        "fd" => Box::new(Fd(SysInfo::initial("fd", FileNr::read()))),
+        "cpu" => Box::new(Cpu {
+            last_seen: SysInfo::initial("cpu", CpuTimes::read()),
+            cpu: 0f64,
+            cores: Vec::new(),
+            steal: 0f64,
+            iowait: 0f64,
+            switches: (0, 0),
+        }),
+        "cputemp" => Box::new(CpuTemp {
+            zone: config.thermal_zone,
+            temp: SysInfo::cpu_temp(config.thermal_zone)
+                .inspect_err(|err| info!("Not reporting cputemp: {err}"))
+                .ok(),
+        }),
+        "disktemp" => {
+            let temps = SysInfo::disk_temps();
+            if temps.is_empty() {
+                info!("Not reporting disktemp: no NVMe or drivetemp sensors in /sys/class/hwmon");
+            }
+            Box::new(DiskTemp(temps))
+        }
+        "psi" => Box::new(Psi(SysInfo::psi()
+            .inspect_err(|err| info!("Not reporting pressure stall information: {err}"))
+            .ok())),
+        _ => unreachable!("unknown collector {name}"),
+    }
+}
+
+struct Net {
+    interfaces: Vec<Interface>,
+    /// `--all-interfaces`, report the sum of every interface's deltas
+    all: bool,
+}
+
+impl Collector for Net {
+    fn name(&self) -> &'static str {
+        "net"
+    }
+
+    fn collect(&mut self) {
+        for interface in &mut self.interfaces {
+            interface.refresh();
+        }
+    }
+
+    /// Summing happens after each interface has worked out its own delta,
+    /// so a counter reset on one costs only that interface's interval
+    fn report(&self, metrics: &mut Metrics) {
+        let interfaces = self.interfaces.iter().map(|interface| InterfaceMetrics {
+            name: interface.name.clone(),
+            rx: interface.rx,
+            tx: interface.tx,
+            rx_total: interface.last_seen_rx,
+            tx_total: interface.last_seen_tx,
+            suffix: interface.suffix.clone(),
+        });
+        metrics.interfaces = match self.all {
+            true => vec![interfaces.fold(
+                InterfaceMetrics {
+                    name: "all".to_string(),
+                    ..InterfaceMetrics::default()
+                },
+                |mut all, interface| {
+                    all.rx += interface.rx;
+                    all.tx += interface.tx;
+                    all.rx_total += interface.rx_total;
+                    all.tx_total += interface.tx_total;
+                    all
+                },
+            )],
+            false => interfaces.collect(),
+        };
+    }
+
+    /// Interfaces that are still watched keep their counters so the next
+    /// sample's deltas stay correct
+    fn reload(&mut self, config: &Config) {
+        self.all = config.all_interfaces;
+        let mut previous = mem::take(&mut self.interfaces);
+        for interface in SysInfo::interfaces(&config.interfaces) {
+            let interface = match previous.iter().position(|old| old.name == interface.name) {
+                Some(i) => Interface {
+                    suffix: interface.suffix,
+                    ..previous.swap_remove(i)
+                },
+                None => interface,
+            };
+            self.interfaces.push(interface);
+        }
+    }
+}
+
+struct NetErrors(Vec<InterfaceErrors>);
+
+impl Collector for NetErrors {
+    fn name(&self) -> &'static str {
+        "net-errors"
+    }
+
+    fn collect(&mut self) {
+        for interface in &mut self.0 {
+            interface.refresh();
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.interface_errors = self
+            .0
+            .iter()
+            .map(|interface| InterfaceErrorMetrics {
+                name: interface.name.clone(),
+                errors: interface.errors,
+                totals: interface.last_seen,
+                suffix: interface.suffix.clone(),
+            })
+            .collect();
+    }
+
+    /// Keeps the counters of interfaces that are still watched, as for `net`
+    fn reload(&mut self, config: &Config) {
+        let mut previous = mem::take(&mut self.0);
+        for interface in SysInfo::interface_errors(&config.interfaces) {
+            let interface = match previous.iter().position(|old| old.name == interface.name) {
+                Some(i) => InterfaceErrors {
+                    suffix: interface.suffix,
+                    ..previous.swap_remove(i)
+                },
+                None => interface,
+            };
+            self.0.push(interface);
+        }
+    }
+}
+
+/// Nothing carries over between reads, so a reload just starts again
+struct Link(Vec<LinkMetrics>);
+
+impl Collector for Link {
+    fn name(&self) -> &'static str {
+        "link"
+    }
+
+    /// An interface that can't be read counts as down, it may have gone
+    fn collect(&mut self) {
+        for link in &mut self.0 {
+            (link.up, link.speed) = SysInfo::link(&link.name).unwrap_or((false, None));
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.links = self.0.clone();
+    }
+
+    fn reload(&mut self, config: &Config) {
+        self.0 = SysInfo::watched_interfaces(&config.interfaces)
+            .map(|(name, suffix)| LinkMetrics {
+                name,
+                up: false,
+                speed: None,
+                suffix,
+            })
+            .collect();
+    }
+}
+
+/// The last counters read and the retransmits since the ones before, `None`
+/// when /proc/net/snmp couldn't be read at startup
+struct Tcp(Option<(TcpStats, u64)>);
+
+impl Collector for Tcp {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn collect(&mut self) {
+        let Some((last_seen, retrans)) = &mut self.0 else {
+            return;
+        };
+        match TcpStats::read() {
+            Ok(stats) => {
+                *retrans = stats.retransmits_since(last_seen);
+                *last_seen = stats;
+            }
+            Err(err) => {
+                *retrans = 0;
+                SysInfo::collect_failed("tcp", err);
+            }
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.tcp = self.0.map(|(stats, _)| stats);
+        metrics.tcp_retrans = self.0.map(|(_, retrans)| retrans);
+    }
+}
+
+/// Entries and table size, `None` when nf_conntrack wasn't loaded at
+/// startup
+struct Conntrack(Option<(u64, u64)>);
+
+impl Collector for Conntrack {
+    fn name(&self) -> &'static str {
+        "conntrack"
+    }
+
+    fn collect(&mut self) {
+        if let Some(conntrack) = &mut self.0 {
+            SysInfo::update("conntrack", conntrack, SysInfo::conntrack());
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.conntrack = self.0.map(|(count, _)| count);
+        metrics.conntrack_pct = self
+            .0
+            .map(|(count, max)| SysInfo::conntrack_pct(count, max));
+    }
+}
+
+struct Uptime(f32);
+
+impl Collector for Uptime {
+    fn name(&self) -> &'static str {
+        "uptime"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("uptime", &mut self.0, SysInfo::uptime());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.uptime = Some(self.0);
+    }
+}
+
+struct BootTime(u64);
+
+impl Collector for BootTime {
+    fn name(&self) -> &'static str {
+        "boottime"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("boottime", &mut self.0, SysInfo::boot_time());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.boot_time = Some(self.0);
+    }
+}
+
+struct AvailMem {
+    percent: f64,
+    bytes: u64,
+}
+
+impl Collector for AvailMem {
+    fn name(&self) -> &'static str {
+        "availmem"
+    }
+
+    fn collect(&mut self) {
+        match MemInfo::read() {
+            Ok(meminfo) => {
+                self.percent = SysInfo::avail_mem(&meminfo);
+                self.bytes = SysInfo::avail_mem_bytes(&meminfo);
+            }
+            Err(err) => SysInfo::collect_failed("availmem", err),
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.avail_mem = Some(self.percent);
+        metrics.avail_mem_bytes = Some(self.bytes);
+    }
+}
+
+struct MemBreakdown {
+    percent: [f64; 4],
+    bytes: [u64; 4],
+}
+
+impl Collector for MemBreakdown {
+    fn name(&self) -> &'static str {
+        "membreakdown"
+    }
+
+    fn collect(&mut self) {
+        match MemInfo::read() {
+            Ok(meminfo) => {
+                self.percent = SysInfo::mem_breakdown_percent(&meminfo);
+                self.bytes = SysInfo::mem_breakdown(&meminfo);
+            }
+            Err(err) => SysInfo::collect_failed("membreakdown", err),
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.mem_breakdown = Some(self.percent);
+        metrics.mem_breakdown_bytes = Some(self.bytes);
+    }
+}
+
+struct SwapUsed(f64);
+
+impl Collector for SwapUsed {
+    fn name(&self) -> &'static str {
+        "swapused"
+    }
+
+    fn collect(&mut self) {
+        match MemInfo::read() {
+            Ok(meminfo) => self.0 = SysInfo::swap_used(&meminfo),
+            Err(err) => SysInfo::collect_failed("swapused", err),
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.swap_used = Some(self.0);
+    }
+}
+
+struct DiskFree(Vec<Filesystem>);
+
+impl Collector for DiskFree {
+    fn name(&self) -> &'static str {
+        "diskfree"
+    }
+
+    fn collect(&mut self) {
+        for filesystem in &mut self.0 {
+            filesystem.refresh();
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.filesystems = self
+            .0
+            .iter()
+            .map(|filesystem| FilesystemMetrics {
+                path: filesystem.path.clone(),
+                free: filesystem.disk_free,
+                free_bytes: filesystem.free_bytes,
+                inodes_free: filesystem.inode_free,
+                read_only: filesystem.read_only,
+                suffix: filesystem.suffix.clone(),
+            })
+            .collect();
+    }
+
+    fn reload(&mut self, config: &Config) {
+        self.0 = SysInfo::filesystems(&SysInfo::filesystem_paths(config));
+    }
+}
+
+struct DiskIo(Vec<Disk>);
+
+impl Collector for DiskIo {
+    fn name(&self) -> &'static str {
+        "disk-io"
+    }
+
+    fn collect(&mut self) {
+        for disk in &mut self.0 {
+            disk.refresh();
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.disks = self
+            .0
+            .iter()
+            .map(|disk| DiskMetrics {
+                device: disk.name.clone(),
+                read: disk.read,
+                written: disk.written,
+                read_total: disk.last_seen_read,
+                written_total: disk.last_seen_written,
+            })
+            .collect();
+    }
+
+    /// Like interfaces, devices that are still watched keep their counters
+    fn reload(&mut self, config: &Config) {
+        let mut previous = mem::take(&mut self.0);
+        for disk in SysInfo::disks(&config.disks) {
+            let disk = match previous.iter().position(|old| old.name == disk.name) {
+                Some(i) => previous.swap_remove(i),
+                None => disk,
+            };
+            self.0.push(disk);
+        }
+    }
+}
+
+struct Load {
+    /// Read once, the core count doesn't change while we run
+    cores: f32,
+    load_avg: [f32; 3],
+    /// The load averages scaled to percent of the cores
+    load: [f32; 3],
+}
+
+impl Collector for Load {
+    fn name(&self) -> &'static str {
+        "load"
+    }
+
+    fn collect(&mut self) {
+        match LoadAvg::read() {
+            Ok(load_avg) => {
+                self.load_avg = load_avg.load;
+                self.load = SysInfo::load(load_avg.load, self.cores);
+            }
+            Err(err) => SysInfo::collect_failed("load", err),
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.load_avg = Some(self.load_avg);
+        metrics.load = Some(self.load);
+    }
+}
+
+struct Procs(LoadAvg);
+
+impl Collector for Procs {
+    fn name(&self) -> &'static str {
+        "procs"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("procs", &mut self.0, LoadAvg::read());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.procs = Some(self.0.total);
+        metrics.procs_running = Some(self.0.running);
+        metrics.procs_total = Some(self.0.total);
+    }
+}
+
+/// Scans every process, which is why it's opt in
+struct Zombies(u64);
+
+impl Collector for Zombies {
+    fn name(&self) -> &'static str {
+        "zombies"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("zombies", &mut self.0, SysInfo::zombies());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.zombies = Some(self.0);
+    }
+}
+
+struct Entropy(u64);
+
+impl Collector for Entropy {
+    fn name(&self) -> &'static str {
+        "entropy"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("entropy", &mut self.0, SysInfo::entropy());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.entropy = Some(self.0);
+    }
+}
+
+/// The release, which only changes with a reboot but is cheap to read again
+struct Kernel(String);
+
+impl Collector for Kernel {
+    fn name(&self) -> &'static str {
+        "kernel"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("kernel", &mut self.0, SysInfo::kernel_release());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.kernel = (!self.0.is_empty()).then(|| self.0.clone());
+    }
+}
+
+struct Fd(FileNr);
+
+impl Collector for Fd {
+    fn name(&self) -> &'static str {
+        "fd"
+    }
+
+    fn collect(&mut self) {
+        SysInfo::update("fd", &mut self.0, FileNr::read());
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.fd_used = Some(self.0.used());
+        metrics.fd_used_pct = Some(self.0.used_percent());
+    }
+}
+
+struct Cpu {
+    last_seen: CpuTimes,
+    cpu: f64,
+    /// Busy percent by core number
+    cores: Vec<(u32, f64)>,
+    steal: f64,
+    iowait: f64,
+    /// Context switches and interrupts since the previous read
+    switches: (u64, u64),
+}
+
+impl Collector for Cpu {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn collect(&mut self) {
+        match CpuTimes::read() {
+            Ok(cpu_times) => {
+                self.cpu = cpu_times.busy_since(&self.last_seen);
+                self.cores = cpu_times.cores_busy_since(&self.last_seen);
+                self.steal = cpu_times.steal_since(&self.last_seen);
+                self.iowait = cpu_times.iowait_since(&self.last_seen);
+                self.switches = cpu_times.switches_since(&self.last_seen);
+                self.last_seen = cpu_times;
+            }
+            Err(err) => SysInfo::collect_failed("cpu", err),
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.cpu = Some(self.cpu);
+        metrics.cpu_cores = self.cores.clone();
+        metrics.cpu_steal = Some(self.steal);
+        metrics.cpu_iowait = Some(self.iowait);
+        metrics.ctxt = Some(self.switches.0);
+        metrics.intr = Some(self.switches.1);
+    }
+}
+
+/// `temp` is `None` when there were no sensors at startup, many VMs have none
+struct CpuTemp {
+    zone: Option<u32>,
+    temp: Option<f64>,
+}
+
+impl Collector for CpuTemp {
+    fn name(&self) -> &'static str {
+        "cputemp"
+    }
+
+    fn collect(&mut self) {
+        if let Some(temp) = &mut self.temp {
+            SysInfo::update("cputemp", temp, SysInfo::cpu_temp(self.zone));
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.cpu_temp = self.temp;
+    }
+
+    fn reload(&mut self, config: &Config) {
+        if self.zone != config.thermal_zone {
+            self.zone = config.thermal_zone;
+            self.temp = SysInfo::cpu_temp(self.zone)
+                .inspect_err(|err| info!("Not reporting cputemp: {err}"))
+                .ok();
+        }
+    }
+}
+
+/// Looked for again each time, so a hot-plugged drive shows up and one
+/// that's pulled stops being sent
+struct DiskTemp(Vec<(String, f64)>);
+
+impl Collector for DiskTemp {
+    fn name(&self) -> &'static str {
+        "disktemp"
+    }
+
+    fn collect(&mut self) {
+        self.0 = SysInfo::disk_temps();
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.disk_temps = self.0.clone();
+    }
+}
+
+/// `None` when the kernel had no PSI at startup, it isn't looked for again
+struct Psi(Option<[f64; 3]>);
+
+impl Collector for Psi {
+    fn name(&self) -> &'static str {
+        "psi"
+    }
+
+    fn collect(&mut self) {
+        if let Some(psi) = &mut self.0 {
+            SysInfo::update("psi", psi, SysInfo::psi());
+        }
+    }
+
+    fn report(&self, metrics: &mut Metrics) {
+        metrics.psi = self.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        SysInfo,
+        testing::{self, TempDir},
+    };
+    use std::fs;
+
+    /// The next sample's metrics, with their values as they're sent
+    fn named(info: &mut SysInfo) -> Vec<(String, String)> {
+        info.sample()
+            .named()
+            .into_iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .collect()
+    }
+
+    /// A fake /proc with `cores` processors and the load averages
+    /// `loadavg`, read for the rest of the calling test
+    fn proc_root(proc: &TempDir, cores: usize, loadavg: &str) {
+        let cpuinfo: String = (0..cores)
+            .map(|core| format!("processor\t: {core}\nmodel name\t: Xeon\n\n"))
+            .collect();
+        proc.write("cpuinfo", &cpuinfo);
+        proc.write("loadavg", loadavg);
+        crate::set_roots(&testing::config(&[
+            "--proc-root",
+            proc.path().to_str().unwrap(),
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]));
+    }
+
+    #[test]
+    fn load() {
+        let proc = TempDir::new("load");
+        proc_root(&proc, 4, "2.00 1.00 0.50 3/200 4321\n");
+        let config = testing::config(&[
+            "--metrics",
+            "load,load5,load15",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let mut info = SysInfo::new(&config);
+        let named = named(&mut info);
+        let expected = [("load", "50"), ("load5", "25"), ("load15", "13")];
+        assert_eq!(
+            named,
+            expected.map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+        assert_eq!(info.sample().load_avg, Some([2.0, 1.0, 0.5]));
+    }
+
+    #[test]
+    fn procs() {
+        let proc = TempDir::new("procs");
+        proc_root(&proc, 1, "0.10 0.20 0.30 3/1043 12345\n");
+        let config = testing::config(&[
+            "--metrics",
+            "procs,procs-running,procs-total",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let mut info = SysInfo::new(&config);
+        proc.write("loadavg", "0.10 0.20 0.30 7/2086 12399\n");
+        info.refresh();
+        let named = named(&mut info);
+        let expected = [
+            ("procs", "2086"),
+            ("procs-running", "7"),
+            ("procs-total", "2086"),
+        ];
+        assert_eq!(
+            named,
+            expected.map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+    }
+
+    #[test]
+    fn cores_cached() {
+        let proc = TempDir::new("cores");
+        proc_root(&proc, 2, "1.00 1.00 1.00 1/100 1\n");
+        let config = testing::config(&["--metrics", "load", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        assert_eq!(info.sample().load, Some([50.0; 3]));
+        // Counted at startup, not on every refresh
+        proc_root(&proc, 4, "1.00 1.00 1.00 1/100 1\n");
+        info.refresh();
+        assert_eq!(info.sample().load, Some([50.0; 3]));
+        // With no processor lines the online CPUs are counted instead
+        proc_root(&proc, 0, "1.00 1.00 1.00 1/100 1\n");
+        let sys = TempDir::new("cores-online");
+        sys.write("devices/system/cpu/online", "0-3\n");
+        crate::set_roots(&testing::config(&[
+            "--proc-root",
+            proc.path().to_str().unwrap(),
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]));
+        assert_eq!(SysInfo::cores(), 4.0);
+        // And with neither, whatever the scheduler allows, never 0
+        fs::remove_file(sys.join("devices/system/cpu/online")).unwrap();
+        assert!(SysInfo::cores() >= 1.0);
+    }
+
+    #[test]
+    fn interfaces() {
+        let sys = TempDir::new("interfaces");
+        let counters = |interface, rx, tx| testing::counters(&sys, interface, rx, tx);
+        counters("eth0", 1000, 500);
+        counters("wg0", 200, 100);
+        // docker0 isn't there, and is left out rather than failing startup
+        let config = testing::config(&[
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "--metrics",
+            "net-rx,net-tx",
+            "localhost",
+            "myapp",
+            "/",
+            "eth0,wg0,docker0",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        counters("eth0", 1600, 800);
+        counters("wg0", 250, 110);
+        info.refresh();
+        let named = named(&mut info);
+        let expected = [
+            ("net-rx.eth0", "600"),
+            ("net-tx.eth0", "300"),
+            ("net-rx.wg0", "50"),
+            ("net-tx.wg0", "10"),
+        ];
+        for (name, value) in expected {
+            assert!(
+                named.contains(&(name.to_string(), value.to_string())),
+                "{name} {named:?}"
+            );
+        }
+        assert!(named.iter().all(|(name, _)| !name.contains("docker0")));
+    }
+
+    #[test]
+    fn all_interfaces() {
+        let sys = TempDir::new("all-interfaces");
+        let counters = |interface, rx, tx| testing::counters(&sys, interface, rx, tx);
+        for (interface, link_type) in [("eth0", "1\n"), ("eth1", "1\n"), ("lo", "772\n")] {
+            counters(interface, 1000, 500);
+            sys.write(&format!("class/net/{interface}/type"), link_type);
+        }
+        let config = testing::config(&[
+            "--all-interfaces",
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "--metrics",
+            "net-rx,net-tx",
+            "localhost",
+            "myapp",
+            "/",
+            "auto",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        counters("eth0", 1600, 800);
+        // eth1 was reset, which only loses its own interval
+        counters("eth1", 40, 20);
+        counters("lo", 900000, 900000);
+        info.refresh();
+        let metrics = info.sample();
+        assert_eq!(metrics.interfaces.len(), 1);
+        assert_eq!(
+            (metrics.interfaces[0].rx, metrics.interfaces[0].tx),
+            (600, 300)
+        );
+        let named = metrics.named();
+        assert!(
+            named
+                .iter()
+                .any(|(name, value)| name == "net-rx" && value.to_string() == "600")
+        );
+        counters("eth0", 1700, 900);
+        counters("eth1", 140, 70);
+        info.refresh();
+        assert_eq!(info.sample().interfaces[0].rx, 200);
+    }
+}
@@ -0,0 +1,1577 @@
+use crate::{
+    SysInfo,
+    collectors::{COLLECTORS, OPT_IN},
+    metrics::METRICS,
+    send::host_port,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{IpAddr, Ipv6Addr, ToSocketAddrs, UdpSocket},
+    path::Path,
+    time::Duration,
+};
+
+/// How often metrics are emitted unless `--interval` says otherwise
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The metric path every push format puts before the metric name
+const DEFAULT_PREFIX_TEMPLATE: &str = "{namespace}.{hostname}";
+
+/// How long a resolved destination address is used before it's looked up
+/// again unless `--resolve-ttl` says otherwise
+const DEFAULT_RESOLVE_TTL: Duration = Duration::from_secs(300);
+
+/// Small enough for a datagram to cross a standard 1500 byte MTU with room
+/// for the IP and UDP headers
+const DEFAULT_MAX_PACKET_SIZE: usize = 1400;
+
+/// The standard StatsD port
+const DEFAULT_PORT: u16 = 8125;
+
+/// Environment variables starting with this set options
+const ENV_PREFIX: &str = "UPTIMED_";
+
+/// What the positional arguments are, in order. Each can be given as an
+/// option instead.
+const POSITIONAL: [&str; 4] = ["destination", "namespace", "filesystem", "interface"];
+
+/// Options that take no value on the command line. In the config file they
+/// are written as `name = true`.
+const SWITCHES: &[&str] = &[
+    "once",
+    "sanitize-hostname",
+    "absolute",
+    "local-hostname",
+    "fqdn",
+    "syslog",
+    "machine-id-tag",
+    "all-interfaces",
+    "net-as-counter",
+    "gauge-delta",
+    "no-hostname",
+];
+
+/// Pairs of options that can't both be given. Setting one in a layer that
+/// wins over another, the command line over the file say, drops the other
+/// from the losing layer.
+const EXCLUSIVE: &[(&str, &str)] = &[
+    ("no-hostname", "prefix-template"),
+    ("gauge-delta", "net-as-counter"),
+];
+
+/// Options that do something other than run, and take no value. `main`
+/// looks for `--help` and `--version` before anything else is parsed, the
+/// listings wait for `--proc-root` and `--sys-root`.
+const COMMANDS: &[&str] = &["help", "version", "list-interfaces", "list-filesystems"];
+
+/// What `--list-interfaces` or `--list-filesystems` asked `main` to print
+/// instead of running
+#[derive(Clone, Copy, PartialEq)]
+pub enum List {
+    Interfaces,
+    Filesystems,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+    /// A Unix datagram socket, used when the destination is a path
+    Unix,
+    /// POST to the `--endpoint`
+    Http,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Statsd,
+    Influx,
+    Graphite,
+    Json,
+    /// OTLP/HTTP with the JSON encoding, sent to `--endpoint`
+    Otlp,
+    /// Collectd's plaintext `PUTVAL` commands
+    Collectd,
+}
+
+/// An `http://host[:port]/path` URL that `--format otlp` POSTs samples to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Endpoint {
+    /// Plain HTTP only, an OpenTelemetry collector on the host or the local
+    /// network. The port defaults to 80 and the path to `/`.
+    pub(crate) fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or(format!("Invalid endpoint {url:?}, must start with http://"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("Invalid endpoint {url:?}, no host"));
+        }
+        let (host, port) = Config::split_host_port(authority)
+            .map_err(|err| format!("Invalid endpoint {url:?}: {err}"))?;
+        Ok(Self {
+            host,
+            port: port.unwrap_or(80),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// An `--alert` threshold, `availmem<20` or `load>100`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub metric: String,
+    /// Whether the alert is on above the threshold rather than below it
+    pub above: bool,
+    pub threshold: f64,
+}
+
+impl Alert {
+    /// Whether a value of `metric` sets the alert off
+    pub fn firing(&self, value: f64) -> bool {
+        match self.above {
+            true => value > self.threshold,
+            false => value < self.threshold,
+        }
+    }
+}
+
+/// Where `--syslog` writes samples, one message per line of payload
+#[derive(Clone, Debug, PartialEq)]
+pub struct Syslog {
+    /// The log socket, `/dev/log` unless `--syslog-socket`
+    pub socket: String,
+    /// The facility number, 3 for daemon
+    pub facility: u8,
+    pub tag: String,
+}
+
+impl Syslog {
+    /// Every message is logged at info severity
+    const SEVERITY: u8 = 6;
+
+    /// `<pri>tag[pid]: line`, the header glibc's syslog(3) writes less the
+    /// timestamp, which the log daemon fills in
+    pub(crate) fn message(&self, line: &str) -> String {
+        format!(
+            "<{}>{}[{}]: {line}",
+            self.facility * 8 + Self::SEVERITY,
+            self.tag,
+            std::process::id()
+        )
+    }
+}
+
+/// Everything set on the command line or in the --config file
+pub struct Config {
+    /// Every sample goes to each of these, as host and port or a socket
+    /// path and an unused port. Empty only with `--syslog` or
+    /// `--output-file`.
+    pub destinations: Vec<(String, u16)>,
+    /// Also write every sample to syslog
+    pub syslog: Option<Syslog>,
+    /// Also append every sample to this file
+    pub output_file: Option<String>,
+    /// Where `--format otlp` sends samples, instead of the destinations
+    pub endpoint: Option<Endpoint>,
+    pub namespace: String,
+    /// Mount points, `auto` standing for every real filesystem mounted
+    pub filesystems: Vec<String>,
+    /// Filesystem types `auto` keeps even though they're pseudo
+    /// filesystems, e.g. tmpfs
+    pub include_fs_types: Vec<String>,
+    /// Filesystem types `auto` skips on top of the pseudo filesystems
+    pub exclude_fs_types: Vec<String>,
+    /// Interface names, `auto` standing for every one but loopback
+    pub interfaces: Vec<String>,
+    /// Send net-rx and net-tx summed over the interfaces rather than one
+    /// per interface
+    pub all_interfaces: bool,
+    /// Block devices to report read/write throughput for, none by default
+    pub disks: Vec<String>,
+    /// The thermal zone cputemp reads, the hottest one when `None`
+    pub thermal_zone: Option<u32>,
+    /// Collectors turned off with `--disable`, and the opt in ones
+    /// `--enable` doesn't turn on
+    pub disabled: Vec<String>,
+    /// The metrics that get sent, all of them unless `--metrics` or
+    /// `--disable-metrics` narrow it down
+    pub metrics: Vec<String>,
+    pub interval: Duration,
+    /// Samples to send before exiting, 0 keeps sending
+    pub count: u64,
+    /// The most the first sample is delayed by, 0 sends it straight away
+    pub jitter: Duration,
+    /// How long the destination's resolved address is trusted
+    pub resolve_ttl: Duration,
+    /// Samples kept per destination while it can't be reached, 0 drops them
+    pub buffer_size: usize,
+    /// Times a failed send is tried again before the next interval
+    pub send_retries: u32,
+    /// The largest UDP or Unix datagram, bigger samples are split
+    pub max_packet_size: usize,
+    pub protocol: Protocol,
+    /// The source address UDP samples are sent from, the kernel picks one
+    /// by routing without it
+    pub bind: Option<IpAddr>,
+    pub format: Format,
+    /// Serve /metrics for Prometheus on this address instead of pushing
+    pub prometheus: Option<String>,
+    /// Answer liveness probes on this address while pushing
+    pub health_addr: Option<String>,
+    /// Print one sample to stdout and exit
+    pub once: bool,
+    /// Send availmem, the memory breakdown and diskfree as bytes rather than
+    /// percent
+    pub absolute: bool,
+    /// How many -v were given
+    pub verbosity: u8,
+    /// DogStatsD tags appended to every StatsD line
+    pub tags: Vec<String>,
+    /// Add a `machine_id:` tag from /etc/machine-id as well
+    pub machine_id_tag: bool,
+    /// Thresholds sent as `alert.<metric>`, none unless asked for
+    pub alerts: Vec<Alert>,
+    /// Decimal places for percentages and load, 0 sends whole numbers
+    pub precision: u32,
+    /// The share of samples sent, marked on each StatsD line
+    pub sample_rate: Option<f64>,
+    /// Send net-rx and net-tx as StatsD counters of the interval's bytes
+    pub net_as_counter: bool,
+    /// Send net-rx and net-tx as `+<bytes>` gauge changes, which the server
+    /// adds to the gauge
+    pub gauge_delta: bool,
+    /// What the dots between StatsD name components become, `.` leaves
+    /// them alone
+    pub separator: String,
+    /// Just `{namespace}` under `--no-hostname`, for pipelines that add the
+    /// hostname themselves. The per-interface and per-filesystem suffixes
+    /// still follow the metric name.
+    pub prefix_template: String,
+    /// Take the hostname from gethostname(2), our own UTS namespace, rather
+    /// than /proc/sys/kernel/hostname under `proc_root`
+    pub local_hostname: bool,
+    /// Use the fully qualified name wherever the hostname goes
+    pub fqdn: bool,
+    /// What dots in the hostname become when it's sanitized for the prefix,
+    /// `None` leaves the hostname alone
+    pub sanitize_hostname: Option<String>,
+    pub proc_root: String,
+    pub sys_root: String,
+    /// Only set from the command line
+    pub list: Option<List>,
+}
+
+impl Config {
+    /// Options on the command line win over the positional arguments, which
+    /// win over `UPTIMED_*` environment variables, which win over the
+    /// `--config` file
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        Self::from_sources(args, std::env::vars())
+    }
+
+    /// Whether one of `flags` is on the command line where an option can
+    /// go, so `--namespace --help` is a namespace and not a call for help
+    pub fn has_flag(args: &[String], flags: &[&str]) -> bool {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if flags.contains(&arg.as_str()) {
+                return true;
+            }
+            if let Some(name) = arg.strip_prefix("--")
+                && !SWITCHES.contains(&name)
+                && !COMMANDS.contains(&name)
+            {
+                args.next();
+            }
+        }
+        false
+    }
+
+    /// Like [`Config::from_args`], but with the environment given rather
+    /// than read from the process. `UPTIMED_<NAME>` sets the option
+    /// `--<name>`, so `UPTIMED_RESOLVE_TTL=60` is `--resolve-ttl 60`. They
+    /// win over the config file and lose to the command line.
+    pub fn from_sources(
+        args: &[String],
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut environment = Vec::new();
+        for (var, value) in vars {
+            let Some(name) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if value.trim().is_empty() {
+                return Err(format!("{var} is set but empty"));
+            }
+            environment.push((name.to_lowercase().replace('_', "-"), value));
+        }
+
+        let mut options = Vec::new();
+        let mut positional = Vec::new();
+        let mut verbosity = 0;
+        let mut list = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(vs) = arg
+                .strip_prefix('-')
+                .filter(|vs| !vs.is_empty() && vs.chars().all(|c| c == 'v'))
+            {
+                verbosity += vs.len();
+                continue;
+            }
+            match arg.strip_prefix("--") {
+                Some("list-interfaces") => list = Some(List::Interfaces),
+                Some("list-filesystems") => list = Some(List::Filesystems),
+                Some(name) if SWITCHES.contains(&name) => {
+                    options.push((name.to_string(), "true".to_string()));
+                }
+                Some(name) => {
+                    let value = args.next().ok_or(format!("{arg} requires a value"))?;
+                    options.push((name.to_string(), value.clone()));
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        let config = |settings: &[(String, String)]| {
+            let config = settings.iter().rev().find(|(name, _)| name == "config");
+            config.map(|(_, path)| path.clone())
+        };
+        let mut settings = BTreeMap::new();
+        if let Some(path) = config(&options).or(config(&environment)) {
+            settings.extend(Self::read_file(&path)?);
+        }
+        Self::layer(
+            &mut settings,
+            environment.into_iter().filter(|(name, _)| name != "config"),
+        );
+        match positional.len() {
+            // Everything can come from options or the config file instead
+            0 => {}
+            4 => settings.extend(POSITIONAL.map(String::from).into_iter().zip(positional)),
+            n => {
+                return Err(format!(
+                    "Expected 4 positional arguments ({}), got {n}: {}",
+                    POSITIONAL.join(" "),
+                    positional.join(" ")
+                ));
+            }
+        }
+        Self::layer(
+            &mut settings,
+            options.into_iter().filter(|(name, _)| name != "config"),
+        );
+        // Listing only needs the roots, so the daemon's arguments can be left
+        // out
+        if list.is_some() {
+            for (name, placeholder) in POSITIONAL.iter().zip(["localhost", "uptimed", "/", "lo"]) {
+                settings
+                    .entry(name.to_string())
+                    .or_insert(placeholder.to_string());
+            }
+        }
+        // -v is only a command line flag, so it doesn't go through the
+        // settings the file and environment can set
+        let verbosity = u8::try_from(verbosity).unwrap_or(u8::MAX);
+        Self::from_settings(settings).map(|config| Self {
+            list,
+            verbosity,
+            ..config
+        })
+    }
+
+    /// Puts `layer` over the settings so far, see [`EXCLUSIVE`]
+    fn layer(
+        settings: &mut BTreeMap<String, String>,
+        layer: impl IntoIterator<Item = (String, String)>,
+    ) {
+        let layer: Vec<(String, String)> = layer.into_iter().collect();
+        for (name, _) in &layer {
+            for (one, other) in EXCLUSIVE {
+                match name.as_str() {
+                    name if name == *one => settings.remove(*other),
+                    name if name == *other => settings.remove(*one),
+                    _ => None,
+                };
+            }
+        }
+        settings.extend(layer);
+    }
+
+    /// Reads a TOML file of top level `name = value` pairs using the long
+    /// option names, e.g. `interval = 10`, `namespace = "myapp"` or
+    /// `interface = ["eth0", "wg0"]`. Arrays become the comma separated
+    /// lists the options take.
+    ///
+    /// This is the subset of TOML options need: basic and literal strings,
+    /// whole numbers, booleans and one line arrays of them. Tables,
+    /// multi-line strings, floats and dates are rejected rather than
+    /// misread.
+    fn read_file(path: &str) -> Result<Vec<(String, String)>, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read config file {path}: {err}"))?;
+        let mut settings: Vec<(String, String)> = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let error = |message: &str| format!("{path}:{}: {message}", number + 1);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(error(
+                    "tables aren't supported, options go at the top level",
+                ));
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(error("expected name = value"));
+            };
+            let name = name.trim();
+            if settings.iter().any(|(seen, _)| seen == name) {
+                return Err(error(&format!("{name} is set twice")));
+            }
+            let value = Self::parse_toml_value(value.trim()).map_err(|err| error(&err))?;
+            settings.push((name.to_string(), value));
+        }
+        Ok(settings)
+    }
+
+    /// A string, number, boolean or one line array of them, followed by
+    /// nothing but an optional comment
+    fn parse_toml_value(value: &str) -> Result<String, String> {
+        let (parsed, rest) = match value.strip_prefix('[') {
+            Some(mut rest) => {
+                let mut items = Vec::new();
+                loop {
+                    rest = rest.trim_start();
+                    if let Some(after) = rest.strip_prefix(']') {
+                        break (items.join(","), after);
+                    }
+                    if rest.is_empty() || rest.starts_with('#') {
+                        return Err("unclosed [ in array".to_string());
+                    }
+                    let (item, after) = Self::parse_toml_scalar(rest)?;
+                    items.push(item);
+                    rest = after.trim_start();
+                    match rest.strip_prefix(',') {
+                        Some(after) => rest = after,
+                        None if rest.starts_with(']') => {}
+                        None => return Err("expected , or ] in array".to_string()),
+                    }
+                }
+            }
+            None => Self::parse_toml_scalar(value)?,
+        };
+        match rest.trim_start() {
+            "" => Ok(parsed),
+            rest if rest.starts_with('#') => Ok(parsed),
+            rest => Err(format!("unexpected {rest:?} after the value")),
+        }
+    }
+
+    /// Returns the value and whatever follows it
+    fn parse_toml_scalar(value: &str) -> Result<(String, &str), String> {
+        if let Some(rest) = value.strip_prefix('\'') {
+            let end = rest.find('\'').ok_or("unclosed ' in string")?;
+            return Ok((rest[..end].to_string(), &rest[end + 1..]));
+        }
+        if let Some(rest) = value.strip_prefix('"') {
+            let mut parsed = String::new();
+            let mut chars = rest.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => return Ok((parsed, &rest[i + 1..])),
+                    '\\' => {
+                        let escaped = match chars.next() {
+                            Some((_, 'b')) => '\u{8}',
+                            Some((_, 't')) => '\t',
+                            Some((_, 'n')) => '\n',
+                            Some((_, 'f')) => '\u{c}',
+                            Some((_, 'r')) => '\r',
+                            Some((_, '"')) => '"',
+                            Some((_, '\\')) => '\\',
+                            Some((_, u @ ('u' | 'U'))) => {
+                                let digits = if u == 'u' { 4 } else { 8 };
+                                let hex: String =
+                                    chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                                u32::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .filter(|_| {
+                                        hex.len() == digits
+                                            && hex.chars().all(|c| c.is_ascii_hexdigit())
+                                    })
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| format!("invalid escape \\{u}{hex} in string"))?
+                            }
+                            Some((_, c)) => return Err(format!("invalid escape \\{c} in string")),
+                            None => break,
+                        };
+                        parsed.push(escaped);
+                    }
+                    c => parsed.push(c),
+                }
+            }
+            return Err("unclosed \" in string".to_string());
+        }
+        let end = value
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+            .unwrap_or(value.len());
+        let bare = &value[..end];
+        let valid = bare == "true"
+            || bare == "false"
+            || (!bare.is_empty() && bare.chars().all(|c| c.is_ascii_digit() || c == '_'));
+        match valid {
+            true => Ok((bare.replace('_', ""), &value[end..])),
+            false => Err(format!(
+                "invalid value {bare:?}, strings need quotes and numbers must be whole"
+            )),
+        }
+    }
+
+    /// Checks what parsing can't: that the destinations resolve and the
+    /// interfaces, block devices and filesystems exist, skipping whatever
+    /// `--once`, `--prometheus` or `--disable` mean isn't used. Every
+    /// problem is listed, one per line.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        let enabled = |collector: &str| !self.disabled.iter().any(|name| name == collector);
+        if !self.once && self.prometheus.is_none() {
+            // A socket path may not exist until its agent starts, sends
+            // retry until it does
+            let endpoint = self
+                .endpoint
+                .iter()
+                .map(|endpoint| (endpoint.host.clone(), endpoint.port));
+            for (host, port) in self
+                .destinations
+                .iter()
+                .cloned()
+                .chain(endpoint)
+                .filter(|(host, _)| !host.starts_with('/'))
+            {
+                let address = host_port(&host, port);
+                match (host.as_str(), port).to_socket_addrs() {
+                    Err(err) => {
+                        problems.push(format!("Destination {address} doesn't resolve: {err}"));
+                    }
+                    // Sends go to the first address, from a socket bound to
+                    // --bind's family
+                    Ok(mut addrs) => {
+                        if let Some(bind) = self.bind
+                            && let Some(addr) = addrs.next()
+                            && addr.is_ipv6() != bind.is_ipv6()
+                        {
+                            problems.push(format!(
+                                "Destination {address} resolves to {}, which can't be sent to \
+                                 from bind address {bind}",
+                                addr.ip()
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        if !self.once
+            && self.prometheus.is_none()
+            && let Some(bind) = self.bind
+            && let Err(err) = UdpSocket::bind((bind, 0))
+        {
+            problems.push(format!("Bind address {bind} can't be used: {err}"));
+        }
+        let valid_namespace = self
+            .namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+        if !valid_namespace {
+            let delimiters = match self.namespace.contains([':', '|', '\n']) {
+                true => ", : | and newlines would break the StatsD lines",
+                false => "",
+            };
+            problems.push(format!(
+                "Invalid namespace {:?}, must be letters, digits, _, - and .{delimiters}",
+                self.namespace
+            ));
+        }
+        if enabled("net") || enabled("net-errors") || enabled("link") {
+            for interface in self.interfaces.iter().filter(|name| *name != "auto") {
+                if !Path::new(&format!("{}/class/net/{interface}", self.sys_root)).exists() {
+                    problems.push(format!("Network interface {interface} doesn't exist"));
+                }
+            }
+        }
+        if enabled("disk-io") {
+            for disk in &self.disks {
+                if !Path::new(&format!("{}/class/block/{disk}", self.sys_root)).exists() {
+                    problems.push(format!("Block device {disk} doesn't exist"));
+                }
+            }
+        }
+        if enabled("cputemp")
+            && let Some(zone) = self.thermal_zone
+            && !Path::new(&format!(
+                "{}/class/thermal/thermal_zone{zone}",
+                self.sys_root
+            ))
+            .exists()
+        {
+            problems.push(format!("Thermal zone {zone} doesn't exist"));
+        }
+        if enabled("diskfree") {
+            for filesystem in self.filesystems.iter().filter(|path| *path != "auto") {
+                if let Err(err) = SysInfo::statvfs(filesystem) {
+                    problems.push(format!("Filesystem {filesystem} can't be read: {err}"));
+                }
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems.join("\n")),
+        }
+    }
+
+    fn from_settings(mut settings: BTreeMap<String, String>) -> Result<Self, String> {
+        let syslog = Self::parse_syslog(&mut settings)?;
+        let output_file = settings.remove("output-file");
+        let endpoint = settings
+            .remove("endpoint")
+            .map(|url| Endpoint::parse(&url))
+            .transpose()?;
+        // Samples can go to syslog, a file or an OTLP endpoint alone
+        let destination = match syslog.is_some() || output_file.is_some() || endpoint.is_some() {
+            true => Some(settings.remove("destination")),
+            false => None,
+        };
+        let mut required = |name: &str| {
+            let position = ["first", "second", "third", "fourth"]
+                [POSITIONAL.iter().position(|p| *p == name).unwrap_or(0)];
+            settings.remove(name).ok_or(format!(
+                "No {name} given, pass it as the {position} argument or with --{name}"
+            ))
+        };
+        let destination = match destination {
+            Some(destination) => destination,
+            None => Some(required("destination")?),
+        };
+        let namespace = required("namespace")?;
+        let filesystems = Self::parse_list(&required("filesystem")?, "filesystem")?;
+        let interfaces = Self::parse_list(&required("interface")?, "network interface")?;
+        let all_interfaces = Self::parse_switch(&mut settings, "all-interfaces")?;
+        let include_fs_types = match settings.remove("include-fs-types") {
+            Some(types) => Self::parse_list(&types, "filesystem type")?,
+            None => Vec::new(),
+        };
+        let exclude_fs_types = match settings.remove("exclude-fs-types") {
+            Some(types) => Self::parse_list(&types, "filesystem type")?,
+            None => Vec::new(),
+        };
+        let disks = match settings.remove("disks") {
+            Some(disks) => Self::parse_list(&disks, "disk")?,
+            None => Vec::new(),
+        };
+        let mut disabled = match settings.remove("disable") {
+            Some(disabled) => Self::parse_disabled(&disabled)?,
+            None => Vec::new(),
+        };
+        let enabled = match settings.remove("enable") {
+            Some(enabled) => Self::parse_enabled(&enabled)?,
+            None => Vec::new(),
+        };
+        disabled.extend(
+            OPT_IN
+                .iter()
+                .filter(|name| !enabled.iter().any(|enabled| enabled == *name))
+                .map(|name| name.to_string()),
+        );
+        let thermal_zone = settings
+            .remove("thermal-zone")
+            .map(|zone| {
+                zone.parse()
+                    .map_err(|_| format!("Invalid thermal-zone {zone:?}, must be a zone number"))
+            })
+            .transpose()?;
+        let mut metrics = match settings.remove("metrics") {
+            Some(metrics) => Self::parse_metrics(&metrics)?,
+            None => METRICS.iter().map(|name| name.to_string()).collect(),
+        };
+        if let Some(disabled) = settings.remove("disable-metrics") {
+            let disabled = Self::parse_metrics(&disabled)?;
+            metrics.retain(|metric| !disabled.contains(metric));
+        }
+
+        let port = settings
+            .remove("port")
+            .map(|p| Self::parse_port(&p))
+            .transpose()?;
+        let destinations = match destination {
+            Some(destination) => Self::parse_list(&destination, "destination")?
+                .into_iter()
+                .map(|destination| Self::parse_destination(destination, port))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let interval = match settings.remove("interval") {
+            Some(value) => Self::parse_seconds("interval", &value)?,
+            None => DEFAULT_INTERVAL,
+        };
+        let count = match settings.remove("count") {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Invalid count {value:?}, must be a number"))?,
+            None => 0,
+        };
+        let jitter = match settings.remove("jitter") {
+            Some(value) => value.parse().map(Duration::from_secs).map_err(|_| {
+                format!("Invalid jitter {value:?}, must be a whole number of seconds")
+            })?,
+            None => Duration::ZERO,
+        };
+        let resolve_ttl = match settings.remove("resolve-ttl") {
+            Some(value) => Self::parse_seconds("resolve-ttl", &value)?,
+            None => DEFAULT_RESOLVE_TTL,
+        };
+        let buffer_size = match settings.remove("buffer-size") {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Invalid buffer-size {value:?}, must be a number"))?,
+            None => 0,
+        };
+        let send_retries = match settings.remove("send-retries") {
+            Some(value) => match value.parse() {
+                Ok(retries) if retries <= 10 => retries,
+                _ => return Err(format!("Invalid send-retries {value:?}, must be 0 to 10")),
+            },
+            None => 0,
+        };
+        let max_packet_size = match settings.remove("max-packet-size") {
+            Some(value) => match value.parse() {
+                Ok(size) if size >= 1 => size,
+                _ => {
+                    return Err(format!(
+                        "Invalid max-packet-size {value:?}, must be a number of bytes >= 1"
+                    ));
+                }
+            },
+            None => DEFAULT_MAX_PACKET_SIZE,
+        };
+        let protocol = match settings.remove("protocol").as_deref() {
+            None | Some("udp") => Protocol::Udp,
+            Some("tcp") => Protocol::Tcp,
+            Some(value) => return Err(format!("Invalid protocol {value:?}, must be udp or tcp")),
+        };
+        let bind =
+            match settings.remove("bind") {
+                Some(_) if protocol != Protocol::Udp => {
+                    return Err("--bind only applies to --protocol udp".to_string());
+                }
+                Some(value) => Some(value.parse().map_err(|_| {
+                    format!("Invalid bind address {value:?}, must be an IP address")
+                })?),
+                None => None,
+            };
+        let format = match settings.remove("format").as_deref() {
+            None | Some("statsd") => Format::Statsd,
+            Some("influx") => Format::Influx,
+            Some("graphite") => Format::Graphite,
+            Some("json") => Format::Json,
+            Some("otlp") => Format::Otlp,
+            Some("collectd") => Format::Collectd,
+            Some(value) => {
+                return Err(format!(
+                    "Invalid format {value:?}, must be statsd, influx, graphite, json, otlp \
+                     or collectd"
+                ));
+            }
+        };
+        // OTLP payloads are HTTP requests, and the endpoint takes nothing else
+        match (format == Format::Otlp, &endpoint) {
+            (true, None) => return Err("--format otlp needs --endpoint".to_string()),
+            (false, Some(_)) => return Err("--endpoint needs --format otlp".to_string()),
+            (true, Some(_)) if bind.is_some() => {
+                return Err("--bind only applies to UDP, not --format otlp".to_string());
+            }
+            (true, Some(_)) if !destinations.is_empty() => {
+                return Err(
+                    "--format otlp sends to --endpoint only, give the namespace, \
+                            filesystem and interface as options rather than arguments"
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+        // PUTVAL lines are for Collectd's unixsock plugin, its network
+        // plugin only speaks the binary protocol
+        if format == Format::Collectd
+            && let Some((host, port)) = destinations.iter().find(|(host, _)| !host.starts_with('/'))
+        {
+            return Err(format!(
+                "--format collectd sends to the unixsock plugin's socket path, not {}",
+                host_port(host, *port)
+            ));
+        }
+        let prometheus = settings.remove("prometheus");
+        let once = Self::parse_switch(&mut settings, "once")?;
+        let health_addr = settings.remove("health-addr");
+        if health_addr.is_some() && (once || prometheus.is_some()) {
+            return Err("--health-addr is for pushing, not --once or --prometheus".to_string());
+        }
+        let absolute = Self::parse_switch(&mut settings, "absolute")?;
+        let tags = match settings.remove("tags") {
+            Some(tags) => Self::parse_tags(&tags)?,
+            None => Vec::new(),
+        };
+        let machine_id_tag = Self::parse_switch(&mut settings, "machine-id-tag")?;
+        let alerts = match settings.remove("alert") {
+            Some(alerts) => Self::parse_alerts(&alerts)?,
+            None => Vec::new(),
+        };
+        let precision = match settings.remove("precision") {
+            Some(value) => match value.parse() {
+                Ok(digits) if digits <= 6 => digits,
+                _ => return Err(format!("Invalid precision {value:?}, must be 0 to 6")),
+            },
+            None => 0,
+        };
+        let sample_rate = match settings.remove("sample-rate") {
+            Some(value) => match value.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate <= 1.0 && format != Format::Statsd => {
+                    return Err(format!(
+                        "sample-rate {rate} only applies to --format statsd"
+                    ));
+                }
+                Ok(rate) if rate > 0.0 && rate <= 1.0 => Some(rate),
+                _ => {
+                    return Err(format!(
+                        "Invalid sample-rate {value:?}, must be more than 0 and at most 1"
+                    ));
+                }
+            },
+            None => None,
+        };
+        let net_as_counter = Self::parse_switch(&mut settings, "net-as-counter")?;
+        let separator = match settings.remove("separator") {
+            Some(_) if format != Format::Statsd => {
+                return Err("--separator only applies to --format statsd".to_string());
+            }
+            Some(separator) => Self::parse_separator(separator)?,
+            None => ".".to_string(),
+        };
+        if net_as_counter && format != Format::Statsd {
+            return Err("--net-as-counter only applies to --format statsd".to_string());
+        }
+        let gauge_delta = Self::parse_switch(&mut settings, "gauge-delta")?;
+        match (gauge_delta, net_as_counter) {
+            (true, true) => {
+                return Err("--gauge-delta and --net-as-counter can't both be given".to_string());
+            }
+            (true, false) if format != Format::Statsd => {
+                return Err("--gauge-delta only applies to --format statsd".to_string());
+            }
+            _ => {}
+        }
+        let prefix_template = match (
+            Self::parse_switch(&mut settings, "no-hostname")?,
+            settings.remove("prefix-template"),
+        ) {
+            (true, Some(_)) => {
+                return Err("--no-hostname and --prefix-template can't both be given".to_string());
+            }
+            (true, None) => "{namespace}".to_string(),
+            (false, template) => Self::parse_prefix_template(
+                template.unwrap_or(DEFAULT_PREFIX_TEMPLATE.to_string()),
+            )?,
+        };
+        let dot_replacement = settings.remove("dot-replacement");
+        let local_hostname = Self::parse_switch(&mut settings, "local-hostname")?;
+        let fqdn = Self::parse_switch(&mut settings, "fqdn")?;
+        let sanitize_hostname = match Self::parse_switch(&mut settings, "sanitize-hostname")? {
+            true => Some(Self::parse_dot_replacement(
+                dot_replacement.unwrap_or("_".to_string()),
+            )?),
+            false if dot_replacement.is_some() => {
+                return Err("--dot-replacement needs --sanitize-hostname".to_string());
+            }
+            false => None,
+        };
+        let mut root = |name: &str, default: &str| {
+            let root = settings.remove(name).unwrap_or(default.to_string());
+            root.trim_end_matches('/').to_string()
+        };
+        let proc_root = root("proc-root", "/proc");
+        let sys_root = root("sys-root", "/sys");
+
+        if let Some(name) = settings.keys().next() {
+            return Err(format!("Unknown option {name}"));
+        }
+        Ok(Self {
+            destinations,
+            syslog,
+            output_file,
+            endpoint,
+            namespace,
+            filesystems,
+            include_fs_types,
+            exclude_fs_types,
+            interfaces,
+            all_interfaces,
+            disks,
+            thermal_zone,
+            disabled,
+            metrics,
+            interval,
+            count,
+            jitter,
+            resolve_ttl,
+            buffer_size,
+            send_retries,
+            max_packet_size,
+            protocol,
+            bind,
+            format,
+            prometheus,
+            health_addr,
+            once,
+            absolute,
+            verbosity: 0,
+            tags,
+            machine_id_tag,
+            alerts,
+            precision,
+            sample_rate,
+            net_as_counter,
+            gauge_delta,
+            separator,
+            prefix_template,
+            local_hostname,
+            fqdn,
+            sanitize_hostname,
+            proc_root,
+            sys_root,
+            list: None,
+        })
+    }
+
+    /// `--syslog` and the options that go with it, which are no use without
+    /// it
+    fn parse_syslog(settings: &mut BTreeMap<String, String>) -> Result<Option<Syslog>, String> {
+        let socket = settings.remove("syslog-socket");
+        let facility = settings.remove("syslog-facility");
+        let tag = settings.remove("syslog-tag");
+        if !Self::parse_switch(settings, "syslog")? {
+            return match [&socket, &facility, &tag]
+                .iter()
+                .any(|value| value.is_some())
+            {
+                true => Err(
+                    "--syslog-socket, --syslog-facility and --syslog-tag need --syslog".to_string(),
+                ),
+                false => Ok(None),
+            };
+        }
+        let facility = match facility.as_deref().unwrap_or("daemon") {
+            "kern" => 0,
+            "user" => 1,
+            "mail" => 2,
+            "daemon" => 3,
+            "auth" => 4,
+            "syslog" => 5,
+            "lpr" => 6,
+            "news" => 7,
+            "uucp" => 8,
+            "cron" => 9,
+            "authpriv" => 10,
+            "ftp" => 11,
+            local => match local
+                .strip_prefix("local")
+                .and_then(|n| n.parse::<u8>().ok())
+            {
+                Some(n) if n <= 7 => 16 + n,
+                _ => {
+                    return Err(format!(
+                        "Invalid syslog-facility {local:?}, must be a name like daemon, \
+                         user or local0 to local7"
+                    ));
+                }
+            },
+        };
+        let tag = tag.unwrap_or("uptimed".to_string());
+        if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || "[]:".contains(c)) {
+            return Err(format!(
+                "Invalid syslog-tag {tag:?}, must have no spaces, brackets or colons"
+            ));
+        }
+        Ok(Some(Syslog {
+            socket: socket.unwrap_or("/dev/log".to_string()),
+            facility,
+            tag,
+        }))
+    }
+
+    /// The replacement has to survive sanitizing itself
+    fn parse_dot_replacement(value: String) -> Result<String, String> {
+        match value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            true => Ok(value),
+            false => Err(format!(
+                "Invalid dot-replacement {value:?}, must be letters, digits, _ or -"
+            )),
+        }
+    }
+
+    /// StatsD ends the name at `:`, and `|`, `@` and `#` start the other parts
+    /// of a line
+    fn parse_separator(separator: String) -> Result<String, String> {
+        match !separator.is_empty()
+            && !separator.contains([':', '|', '@', '#'])
+            && !separator.contains(char::is_whitespace)
+        {
+            true => Ok(separator),
+            false => Err(format!(
+                "Invalid separator {separator:?}, can't be empty or have : | @ # or whitespace"
+            )),
+        }
+    }
+
+    /// Only the placeholders `Encoder::prefix` knows how to fill are allowed,
+    /// and none of the StatsD delimiters
+    fn parse_prefix_template(template: String) -> Result<String, String> {
+        if template.contains([':', '|']) || template.contains(char::is_whitespace) {
+            return Err(format!(
+                "Invalid prefix template {template:?}, : | and whitespace would break \
+                 the StatsD lines"
+            ));
+        }
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("Unclosed {{ in prefix template {template:?}"));
+            };
+            let placeholder = &rest[start + 1..start + end];
+            if !["namespace", "hostname", "fqdn", "machine_id"].contains(&placeholder) {
+                return Err(format!(
+                    "Unknown placeholder {{{placeholder}}} in prefix template, \
+                     must be {{namespace}}, {{hostname}}, {{fqdn}} or {{machine_id}}"
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(template)
+    }
+
+    fn parse_enabled(value: &str) -> Result<Vec<String>, String> {
+        let enabled = Self::parse_list(value, "collector")?;
+        if let Some(unknown) = enabled.iter().find(|name| !OPT_IN.contains(&name.as_str())) {
+            return Err(format!(
+                "Collector {unknown:?} can't be enabled, --enable takes {}",
+                OPT_IN.join(", ")
+            ));
+        }
+        Ok(enabled)
+    }
+
+    fn parse_disabled(value: &str) -> Result<Vec<String>, String> {
+        let disabled = Self::parse_list(value, "collector")?;
+        let names: Vec<&str> = COLLECTORS.iter().map(|(name, _)| *name).collect();
+        if let Some(unknown) = disabled.iter().find(|name| !names.contains(&name.as_str())) {
+            return Err(format!(
+                "Unknown collector {unknown:?}, must be one of {}",
+                names.join(", ")
+            ));
+        }
+        Ok(disabled)
+    }
+
+    /// Metrics are named without their per interface, filesystem or device
+    /// suffix, so `net-rx` covers every `net-rx.<interface>`
+    fn parse_metrics(value: &str) -> Result<Vec<String>, String> {
+        let metrics = Self::parse_list(value, "metric")?;
+        if let Some(unknown) = metrics
+            .iter()
+            .find(|name| !METRICS.contains(&name.as_str()))
+        {
+            return Err(format!(
+                "Unknown metric {unknown:?}, must be one of {}",
+                METRICS.join(", ")
+            ));
+        }
+        Ok(metrics)
+    }
+
+    /// `metric<threshold` or `metric>threshold`, for metrics named as in
+    /// `--metrics`
+    fn parse_alerts(value: &str) -> Result<Vec<Alert>, String> {
+        Self::parse_list(value, "alert")?
+            .iter()
+            .map(|alert| {
+                let invalid = || {
+                    format!("Invalid alert {alert:?}, must be metric<threshold or metric>threshold")
+                };
+                let (metric, threshold, above) =
+                    match (alert.split_once('<'), alert.split_once('>')) {
+                        (Some((metric, threshold)), None) => (metric, threshold, false),
+                        (None, Some((metric, threshold))) => (metric, threshold, true),
+                        _ => return Err(invalid()),
+                    };
+                let metric = metric.trim();
+                if !METRICS.contains(&metric) {
+                    return Err(format!(
+                        "Unknown metric {metric:?} in alert {alert:?}, must be one of {}",
+                        METRICS.join(", ")
+                    ));
+                }
+                Ok(Alert {
+                    metric: metric.to_string(),
+                    above,
+                    threshold: threshold.trim().parse().map_err(|_| invalid())?,
+                })
+            })
+            .collect()
+    }
+
+    /// Tags are `name` or `name:value`, using the characters DogStatsD
+    /// allows in tags
+    fn parse_tags(value: &str) -> Result<Vec<String>, String> {
+        let tags = Self::parse_list(value, "tag")?;
+        for tag in &tags {
+            let valid = !tag.starts_with(':')
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.:/".contains(c));
+            if !valid {
+                return Err(format!(
+                    "Invalid tag {tag:?}, must be name or name:value using letters, digits and _-.:/"
+                ));
+            }
+        }
+        Ok(tags)
+    }
+
+    fn parse_switch(settings: &mut BTreeMap<String, String>, name: &str) -> Result<bool, String> {
+        match settings.remove(name).as_deref() {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(value) => Err(format!("Invalid {name} {value:?}, must be true or false")),
+        }
+    }
+
+    /// Splits a `host:port` destination, falling back to `--port` and then
+    /// the StatsD default when no port is given. A destination starting
+    /// with `/` is a Unix socket path, and `--port` doesn't apply to it.
+    /// IPv6 addresses take a port in brackets, `[::1]:8125`, and may be
+    /// given bare without one.
+    pub(crate) fn parse_destination(
+        destination: String,
+        port: Option<u16>,
+    ) -> Result<(String, u16), String> {
+        if destination.starts_with('/') {
+            return Ok((destination, DEFAULT_PORT));
+        }
+        match Self::split_host_port(&destination)? {
+            (host, Some(host_port)) => {
+                if let Some(port) = port.filter(|&port| port != host_port) {
+                    return Err(format!(
+                        "Destination {destination:?} conflicts with --port {port}"
+                    ));
+                }
+                Ok((host, host_port))
+            }
+            (host, None) => Ok((host, port.unwrap_or(DEFAULT_PORT))),
+        }
+    }
+
+    /// The host of `host:port` and the port when there is one
+    fn split_host_port(destination: &str) -> Result<(String, Option<u16>), String> {
+        let (host, host_port) = if let Some(bracketed) = destination.strip_prefix('[') {
+            let (host, rest) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("Invalid destination {destination:?}, missing ]"))?;
+            let host_port = match rest {
+                "" => None,
+                rest => match rest.strip_prefix(':') {
+                    Some(host_port) => Some(host_port),
+                    None => return Err(format!("Invalid destination {destination:?}")),
+                },
+            };
+            (host, host_port)
+        } else if destination.matches(':').count() > 1 {
+            (destination, None)
+        } else {
+            match destination.split_once(':') {
+                Some((host, host_port)) => (host, Some(host_port)),
+                None => (destination, None),
+            }
+        };
+        if host.contains(':') && host.parse::<Ipv6Addr>().is_err() {
+            return Err(format!("Invalid IPv6 address {host:?} in {destination:?}"));
+        }
+        let host_port = host_port.map(Self::parse_port).transpose()?;
+        Ok((host.to_string(), host_port))
+    }
+
+    /// Splits a comma separated argument, rejecting empty entries
+    fn parse_list(value: &str, what: &str) -> Result<Vec<String>, String> {
+        value
+            .split(',')
+            .map(|item| match item.trim() {
+                "" => Err(format!("Empty {what} in {value:?}")),
+                item => Ok(item.to_string()),
+            })
+            .collect()
+    }
+
+    fn parse_port(value: &str) -> Result<u16, String> {
+        match value.parse::<u16>() {
+            Ok(port) if port >= 1 => Ok(port),
+            _ => Err(format!(
+                "Invalid port {value:?}, must be between 1 and 65535"
+            )),
+        }
+    }
+
+    /// The network deltas are bytes per interval, so an interval of 0 would
+    /// make them meaningless. Anything below one second is rejected.
+    fn parse_seconds(name: &str, value: &str) -> Result<Duration, String> {
+        match value.parse::<u64>() {
+            Ok(secs) if secs >= 1 => Ok(Duration::from_secs(secs)),
+            _ => Err(format!(
+                "Invalid {name} {value:?}, must be a whole number of seconds >= 1"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// The positional arguments every test needs, after `options`
+    fn with_options(options: &[&str]) -> Result<Config, String> {
+        let args = [options, &["localhost", "myapp", "/", "lo"]].concat();
+        testing::try_config(&args)
+    }
+
+    #[test]
+    fn interval() {
+        assert_eq!(with_options(&[]).unwrap().interval, Duration::from_secs(60));
+        let config = with_options(&["--interval", "10"]).unwrap();
+        assert_eq!(config.interval, Duration::from_secs(10));
+        for invalid in ["0", "-1", "1.5", "ten", ""] {
+            let err = with_options(&["--interval", invalid]).err().unwrap();
+            assert_eq!(
+                err,
+                format!("Invalid interval {invalid:?}, must be a whole number of seconds >= 1")
+            );
+        }
+    }
+
+    #[test]
+    fn has_flag() {
+        let has_help = |args: &[&str]| Config::has_flag(&testing::args(args), &["--help", "-h"]);
+        assert!(has_help(&["--help"]));
+        assert!(has_help(&["localhost", "myapp", "/", "lo", "-h"]));
+        assert!(has_help(&["--interval", "10", "--once", "--help"]));
+        assert!(has_help(&["--version", "--help"]));
+        // The value of the option before it
+        assert!(!has_help(&[
+            "--namespace",
+            "--help",
+            "localhost",
+            "myapp",
+            "/",
+            "lo"
+        ]));
+        assert!(!has_help(&["--tags", "-h"]));
+        assert!(!has_help(&[]));
+    }
+
+    #[test]
+    fn endpoint_parse() {
+        let endpoint = Endpoint::parse("http://collector:4318/v1/metrics").unwrap();
+        assert_eq!(endpoint.host, "collector");
+        assert_eq!(endpoint.port, 4318);
+        assert_eq!(endpoint.path, "/v1/metrics");
+        let endpoint = Endpoint::parse("http://[::1]").unwrap();
+        assert_eq!(
+            (
+                endpoint.host.as_str(),
+                endpoint.port,
+                endpoint.path.as_str()
+            ),
+            ("::1", 80, "/")
+        );
+        // A port written with a leading zero is still the port given
+        let endpoint = Endpoint::parse("http://collector:08125/v1/metrics").unwrap();
+        assert_eq!(endpoint.port, 8125);
+        assert_eq!(Endpoint::parse("http://collector").unwrap().port, 80);
+        assert!(Endpoint::parse("https://collector:4318/v1/metrics").is_err());
+        assert!(Endpoint::parse("http:///v1/metrics").is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let dir = TempDir::new("config");
+        let path = dir.write(
+            "uptimed.toml",
+            "# Sample config\n\
+             destination = \"statsd.internal:8125\"\n\
+             namespace = \"myapp\"\n\
+             filesystem = [\"/\", \"/tmp\"]\n\
+             interface = [\"lo\"]\n\
+             interval = 30 # seconds\n\
+             protocol = \"tcp\"\n\
+             tags = [\"env:prod\", \"web\"]\n\
+             disable-metrics = [\"swapused\"]\n",
+        );
+        let config = testing::config(&["--config", &path, "--interval", "10"]);
+        assert_eq!(config.destinations, [("statsd.internal".to_string(), 8125)]);
+        assert_eq!(config.namespace, "myapp");
+        assert_eq!(config.filesystems, ["/", "/tmp"]);
+        assert_eq!(config.interfaces, ["lo"]);
+        assert!(config.protocol == Protocol::Tcp);
+        assert_eq!(config.tags, ["env:prod", "web"]);
+        assert!(!config.metrics.contains(&"swapused".to_string()));
+        // The command line wins over the file
+        assert_eq!(config.interval.as_secs(), 10);
+    }
+
+    #[test]
+    fn config_file_strings() {
+        let dir = TempDir::new("config-strings");
+        let path = dir.write(
+            "uptimed.toml",
+            "tags = [\"quote:\\\"\", \"tab:\\t\", \"e:\\u00e9\", \"smile:\\U0001F600\", 'raw:\\n']\n",
+        );
+        let settings = Config::read_file(&path).unwrap();
+        assert_eq!(
+            settings,
+            [(
+                "tags".to_string(),
+                "quote:\",tab:\t,e:\u{e9},smile:\u{1F600},raw:\\n".to_string()
+            )]
+        );
+
+        for (line, error) in [
+            ("namespace = myapp", "invalid value \"myapp\""),
+            ("namespace = \"my\\qapp\"", "invalid escape \\q"),
+            ("namespace = \"\\uD800\"", "invalid escape \\uD800"),
+            ("namespace = \"\\u00\"", "invalid escape \\u00\""),
+        ] {
+            let path = dir.write("bad.toml", &format!("{line}\n"));
+            let err = Config::read_file(&path).unwrap_err();
+            assert!(err.starts_with(&format!("{path}:1: {error}")), "{err}");
+        }
+    }
+
+    #[test]
+    fn environment() {
+        let vars = [
+            ("UPTIMED_DESTINATION", "statsd.internal"),
+            ("UPTIMED_NAMESPACE", "fromenv"),
+            ("UPTIMED_FILESYSTEM", "/"),
+            ("UPTIMED_INTERFACE", "lo"),
+            ("UPTIMED_INTERVAL", "30"),
+            ("PATH", "/usr/bin"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let config = Config::from_sources(&[], vars.clone()).unwrap();
+        assert_eq!(config.namespace, "fromenv");
+        assert_eq!(config.interval.as_secs(), 30);
+
+        let args = testing::args(&["--interval", "10"]);
+        let config = Config::from_sources(&args, vars).unwrap();
+        assert_eq!(config.interval.as_secs(), 10);
+
+        let empty = [("UPTIMED_INTERVAL".to_string(), String::new())];
+        assert!(Config::from_sources(&args, empty).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let config = testing::config(&["nowhere.invalid", "my app", "/no/such/dir", "nosuch0"]);
+        let problems = config.validate().unwrap_err();
+        let problems: Vec<&str> = problems.lines().collect();
+        assert_eq!(problems.len(), 4);
+        assert!(problems[0].starts_with("Destination nowhere.invalid:8125 doesn't resolve"));
+        assert!(problems[1].starts_with("Invalid namespace \"my app\""));
+        assert_eq!(problems[2], "Network interface nosuch0 doesn't exist");
+        assert!(problems[3].starts_with("Filesystem /no/such/dir can't be read"));
+
+        let config = testing::config(&["--once", "localhost", "prod:web", "/", "lo"]);
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "Invalid namespace \"prod:web\", must be letters, digits, _, - and ., : | and \
+             newlines would break the StatsD lines"
+        );
+    }
+
+    #[test]
+    fn destinations() {
+        let destinations = |destination: &str| {
+            Config::from_sources(&testing::args(&[destination, "myapp", "/", "lo"]), [])
+                .map(|config| config.destinations)
+        };
+        assert_eq!(
+            destinations("[::1]:9125").unwrap(),
+            [("::1".to_string(), 9125)]
+        );
+        assert_eq!(
+            destinations("[fd00::1]").unwrap(),
+            [("fd00::1".to_string(), 8125)]
+        );
+        assert_eq!(
+            destinations("fd00::1").unwrap(),
+            [("fd00::1".to_string(), 8125)]
+        );
+        assert_eq!(
+            destinations("127.0.0.1:9125").unwrap(),
+            [("127.0.0.1".to_string(), 9125)]
+        );
+        assert_eq!(
+            destinations("statsd").unwrap(),
+            [("statsd".to_string(), 8125)]
+        );
+        assert!(destinations("[::1").is_err());
+        assert!(destinations("fd00::zz").is_err());
+    }
+
+    #[test]
+    fn collectd_destinations() {
+        let collectd = |destination: &str| {
+            let args = ["--format", "collectd", destination, "myapp", "/", "lo"];
+            Config::from_sources(&testing::args(&args), []).map(|config| config.destinations)
+        };
+        assert_eq!(
+            collectd("/run/collectd-unixsock").unwrap(),
+            [("/run/collectd-unixsock".to_string(), 8125)]
+        );
+        assert_eq!(
+            collectd("collectd:25826").unwrap_err(),
+            "--format collectd sends to the unixsock plugin's socket path, not collectd:25826"
+        );
+    }
+
+    #[test]
+    fn port() {
+        let port = |args: &[&str]| testing::try_config(args).map(|config| config.destinations);
+        assert_eq!(
+            port(&["--port", "9125", "statsd", "myapp", "/", "lo"]).unwrap(),
+            [("statsd".to_string(), 9125)]
+        );
+        // The same port twice is no conflict
+        assert_eq!(
+            port(&["--port", "9125", "statsd:9125", "myapp", "/", "lo"]).unwrap(),
+            [("statsd".to_string(), 9125)]
+        );
+        assert_eq!(
+            port(&["--port", "9125", "statsd:8125", "myapp", "/", "lo"]).unwrap_err(),
+            "Destination \"statsd:8125\" conflicts with --port 9125"
+        );
+        for invalid in ["0", "65536", "-1", "http"] {
+            assert_eq!(
+                port(&["--port", invalid, "statsd", "myapp", "/", "lo"]).unwrap_err(),
+                format!("Invalid port {invalid:?}, must be between 1 and 65535")
+            );
+        }
+        assert!(port(&["statsd:0", "myapp", "/", "lo"]).is_err());
+    }
+
+    #[test]
+    fn bind() {
+        let args = |bind: &str| testing::args(&["--bind", bind, "127.0.0.1", "myapp", "/", "lo"]);
+        let config = Config::from_sources(&args("127.0.0.1"), []).unwrap();
+        assert_eq!(config.bind, Some(IpAddr::from([127, 0, 0, 1])));
+        config.validate().unwrap();
+        // A documentation address no interface here has
+        let problems = Config::from_sources(&args("192.0.2.1"), [])
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert!(
+            problems.starts_with("Bind address 192.0.2.1 can't be used: "),
+            "{problems}"
+        );
+        assert!(Config::from_sources(&args("eth0"), []).is_err());
+        // An IPv4 socket can't reach an IPv6 destination
+        let args = testing::args(&["--bind", "127.0.0.1", "[::1]:8125", "myapp", "/", "lo"]);
+        let problems = Config::from_sources(&args, [])
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            problems,
+            "Destination [::1]:8125 resolves to ::1, which can't be sent to from bind address \
+             127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn separator() {
+        let config = testing::config(&["--separator", "_", "localhost", "myapp", "/", "lo"]);
+        assert_eq!(config.separator, "_");
+        for separator in [":", "|", "", " "] {
+            let args = testing::args(&["--separator", separator, "localhost", "myapp", "/", "lo"]);
+            assert!(Config::from_sources(&args, []).is_err(), "{separator:?}");
+        }
+    }
+
+    #[test]
+    fn no_hostname() {
+        let config = testing::config(&["--no-hostname", "localhost", "prod", "/", "lo"]);
+        assert_eq!(config.prefix_template, "{namespace}");
+        let args = testing::args(&[
+            "--no-hostname",
+            "--prefix-template",
+            "{namespace}.{fqdn}",
+            "localhost",
+            "prod",
+            "/",
+            "lo",
+        ]);
+        assert_eq!(
+            Config::from_sources(&args, []).err().as_deref(),
+            Some("--no-hostname and --prefix-template can't both be given")
+        );
+
+        // The command line wins over the file, whichever of the two it sets
+        let dir = TempDir::new("no-hostname");
+        let path = dir.write(
+            "uptimed.toml",
+            "no-hostname = true
+",
+        );
+        let config = testing::config(&[
+            "--config",
+            &path,
+            "--prefix-template",
+            "{namespace}.{fqdn}",
+            "localhost",
+            "prod",
+            "/",
+            "lo",
+        ]);
+        assert_eq!(config.prefix_template, "{namespace}.{fqdn}");
+        let path = dir.write("uptimed.toml", "prefix-template = \"{hostname}\"\n");
+        let config = testing::config(&[
+            "--config",
+            &path,
+            "--no-hostname",
+            "localhost",
+            "prod",
+            "/",
+            "lo",
+        ]);
+        assert_eq!(config.prefix_template, "{namespace}");
+    }
+}
@@ -0,0 +1,890 @@
+use crate::{Config, Format, Metrics, SysInfo, Value, sysinfo::unix_time};
+use std::time::Duration;
+
+/// Turns a sample into a payload in one of the push formats
+pub struct Encoder {
+    format: Format,
+    namespace: String,
+    /// The rendered prefix template
+    prefix: String,
+    tags: Vec<String>,
+    /// `--sample-rate` as the n of every nth sample, StatsD only
+    sample_every: Option<u64>,
+    /// `--net-as-counter`, StatsD only
+    net_as_counter: bool,
+    /// `--gauge-delta`, StatsD only
+    gauge_delta: bool,
+    /// `--separator`, StatsD only
+    separator: String,
+    /// How often samples are taken, which Collectd wants with each value
+    interval: Duration,
+}
+
+impl Encoder {
+    /// The hostname goes into the prefix with anything that would end a
+    /// StatsD name or a Graphite path, `:`, `|` and whitespace, made `_`.
+    pub fn new(config: &Config, hostname: &str) -> Self {
+        let machine_id =
+            match config.machine_id_tag || config.prefix_template.contains("{machine_id}") {
+                true => SysInfo::machine_id().or_else(|| {
+                    warn!("No machine ID in /etc/machine-id or /var/lib/dbus/machine-id");
+                    None
+                }),
+                false => None,
+            };
+        let mut tags = config.tags.clone();
+        if config.machine_id_tag
+            && let Some(id) = &machine_id
+        {
+            tags.push(format!("machine_id:{id}"));
+        }
+        Self {
+            format: config.format,
+            namespace: config.namespace.clone(),
+            prefix: Self::prefix(config, hostname, machine_id.as_deref()),
+            tags,
+            sample_every: config
+                .sample_rate
+                .map(|rate| ((1.0 / rate).round() as u64).max(1)),
+            net_as_counter: config.net_as_counter,
+            gauge_delta: config.gauge_delta,
+            separator: config.separator.clone(),
+            interval: config.interval,
+        }
+    }
+
+    /// Whether `--sample-rate` keeps this sample. A rate of 0.25 sends the
+    /// first sample and every 4th after it, rates that aren't 1/n are
+    /// rounded to the nearest n.
+    pub fn due(&self, metrics: &Metrics) -> bool {
+        match self.sample_every {
+            Some(every) => metrics.sends.saturating_sub(1).is_multiple_of(every),
+            None => true,
+        }
+    }
+
+    /// The metrics in the configured push format
+    pub fn encode(&self, metrics: &Metrics) -> String {
+        match self.format {
+            Format::Statsd => self.serialize(metrics),
+            Format::Influx => self.serialize_influx(metrics),
+            Format::Graphite => self.serialize_graphite(metrics),
+            Format::Json => self.serialize_json(metrics),
+            Format::Otlp => self.serialize_otlp(metrics),
+            Format::Collectd => self.serialize_collectd(metrics),
+        }
+    }
+
+    /// Format metrics for statsd
+    /// <https://github.com/statsd/statsd/blob/master/docs/metric_types.md>
+    /// Everything we report is a gauge apart from `sends`, a counter that
+    /// goes up by one with each sample. Tags use the DogStatsD extension
+    /// <https://docs.datadoghq.com/developers/dogstatsd/datagram_shell/>
+    /// With `--sample-rate` every line says so, letting the server scale
+    /// `sends` back up to every sample taken.
+    ///
+    /// `--net-as-counter` sends net-rx and net-tx as counters of the
+    /// interval's bytes instead. The server then adds up what arrives in
+    /// each flush and reports a rate, bytes per second, so hosts can be
+    /// summed and the flush interval needn't match ours. A flush with no
+    /// sample in it shows 0 where a gauge would have kept its last value.
+    ///
+    /// `--gauge-delta` sends them as gauge changes, `net-rx:+1500|g`, so the
+    /// server's gauge counts up the bytes since it started. A change can't
+    /// be negative, the gauge would count down, so an interval whose counter
+    /// was reset or wrapped is sent as `+0`.
+    pub fn serialize(&self, metrics: &Metrics) -> String {
+        let prefix = &self.prefix;
+        // The rate samples are actually sent at, 0.3 is every 3rd
+        let rate = match self.sample_every {
+            Some(every) => format!("|@{}", 1.0 / every as f64),
+            None => String::new(),
+        };
+        let suffix = if self.tags.is_empty() {
+            rate
+        } else {
+            format!("{rate}|#{}", self.tags.join(","))
+        };
+        metrics
+            .named()
+            .iter()
+            .map(|(name, value)| {
+                let base = name.split('.').next();
+                let path = Self::metric_path(prefix, name, &self.separator);
+                match value {
+                    Value::Count(_) => format!("{path}:1|c{suffix}\n"),
+                    _ if self.net_as_counter && matches!(base, Some("net-rx" | "net-tx")) => {
+                        format!("{path}:{value}|c{suffix}\n")
+                    }
+                    // The interval's bytes, which Interface::refresh never
+                    // lets go below 0
+                    _ if self.gauge_delta && matches!(base, Some("net-rx" | "net-tx")) => {
+                        format!("{path}:+{value}|g{suffix}\n")
+                    }
+                    _ => format!("{path}:{value}|g{suffix}\n"),
+                }
+            })
+            .collect()
+    }
+
+    /// `prefix.name` with `separator` between the components. The name is
+    /// `[alert.]metric[.device]`, and a device can have dots of its own, a
+    /// VLAN like `eth0.100`, that aren't separators.
+    fn metric_path(prefix: &str, name: &str, separator: &str) -> String {
+        let (alert, name) = match name.strip_prefix("alert.") {
+            Some(name) => (Some("alert"), name),
+            None => (None, name),
+        };
+        let (metric, device) = match name.split_once('.') {
+            Some((metric, device)) => (metric, Some(device)),
+            None => (name, None),
+        };
+        let components: Vec<&str> = [Some(prefix), alert, Some(metric), device]
+            .into_iter()
+            .flatten()
+            .collect();
+        components.join(separator)
+    }
+
+    /// Format metrics as a single InfluxDB line protocol point, timestamped
+    /// in nanoseconds
+    /// <https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/>
+    pub fn serialize_influx(&self, metrics: &Metrics) -> String {
+        let escape = |s: &str| {
+            s.replace('\\', "\\\\")
+                .replace(',', "\\,")
+                .replace('=', "\\=")
+                .replace(' ', "\\ ")
+        };
+        let fields: Vec<String> = metrics
+            .named()
+            .iter()
+            .map(|(name, value)| {
+                let name = escape(&name.replace('-', "_"));
+                match value {
+                    Value::Int(value) | Value::Count(value) => format!("{name}={value}i"),
+                    Value::Float(value) => format!("{name}={value}"),
+                }
+            })
+            .collect();
+        format!(
+            "{},host={} {} {}\n",
+            escape(&self.namespace),
+            escape(&metrics.hostname),
+            fields.join(","),
+            unix_time().as_nanos(),
+        )
+    }
+
+    /// Format metrics for Graphite's plaintext protocol, every line sharing
+    /// one timestamp
+    /// <https://graphite.readthedocs.io/en/latest/feeding-carbon.html>
+    pub fn serialize_graphite(&self, metrics: &Metrics) -> String {
+        let prefix = &self.prefix;
+        let timestamp = unix_time().as_secs();
+        metrics
+            .named()
+            .iter()
+            .map(|(name, value)| format!("{prefix}.{name} {value} {timestamp}\n"))
+            .collect()
+    }
+
+    /// Format metrics as one JSON object on a line, with the host and a Unix
+    /// timestamp in seconds. Names use `_` like Influx fields, counts are
+    /// integers and percentages always have a decimal point.
+    pub fn serialize_json(&self, metrics: &Metrics) -> String {
+        let escape = Self::json_escape;
+        let mut json = format!(
+            "{{\"host\":\"{}\",\"timestamp\":{}",
+            escape(&metrics.hostname),
+            unix_time().as_secs()
+        );
+        for (name, value) in metrics.named() {
+            let name = escape(&name.replace('-', "_"));
+            match value {
+                Value::Int(value) | Value::Count(value) => {
+                    json += &format!(",\"{name}\":{value}");
+                }
+                // Debug keeps the `.0` Display drops, named() has already
+                // left out NaN and inf, which JSON can't hold
+                Value::Float(value) => json += &format!(",\"{name}\":{value:?}"),
+            }
+        }
+        json + "}\n"
+    }
+
+    /// Format metrics as an OTLP/HTTP export request in the JSON encoding,
+    /// every metric a gauge named `<namespace>.<metric>` apart from `sends`,
+    /// a cumulative sum. The host is a resource attribute.
+    /// <https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding>
+    pub fn serialize_otlp(&self, metrics: &Metrics) -> String {
+        let escape = Self::json_escape;
+        let time = unix_time().as_nanos();
+        let data_points: Vec<String> = metrics
+            .named()
+            .iter()
+            .map(|(name, value)| {
+                let name = escape(&format!("{}.{name}", self.namespace));
+                // int64 values are strings in the JSON encoding
+                let point = match value {
+                    Value::Int(value) | Value::Count(value) => format!("\"asInt\":\"{value}\""),
+                    Value::Float(value) => format!("\"asDouble\":{value:?}"),
+                };
+                let points = format!("\"dataPoints\":[{{\"timeUnixNano\":\"{time}\",{point}}}]");
+                match value {
+                    Value::Count(_) => format!(
+                        "{{\"name\":\"{name}\",\"sum\":{{\"aggregationTemporality\":2,\
+                         \"isMonotonic\":true,{points}}}}}"
+                    ),
+                    _ => format!("{{\"name\":\"{name}\",\"gauge\":{{{points}}}}}"),
+                }
+            })
+            .collect();
+        format!(
+            "{{\"resourceMetrics\":[{{\"resource\":{{\"attributes\":[{{\"key\":\"host.name\",\
+             \"value\":{{\"stringValue\":\"{}\"}}}}]}},\"scopeMetrics\":[{{\"scope\":\
+             {{\"name\":\"uptimed\",\"version\":\"{}\"}},\"metrics\":[{}]}}]}}]}}",
+            escape(&metrics.hostname),
+            env!("CARGO_PKG_VERSION"),
+            data_points.join(",")
+        )
+    }
+
+    /// Format metrics as Collectd plaintext commands, one `PUTVAL` per
+    /// metric identified as `host/plugin[-instance]/type[-instance]`. Each
+    /// metric is mapped to the plugin and types.db type Collectd's own
+    /// plugins use where there is one, and per device metrics take the
+    /// device as the plugin instance. The namespace isn't used, Collectd
+    /// groups by host and plugin.
+    /// <https://github.com/collectd/collectd/wiki/Plain-text-protocol>
+    pub fn serialize_collectd(&self, metrics: &Metrics) -> String {
+        // `/` separates the parts of an identifier and `"` would end it
+        let part = |s: &str| s.replace(['/', '"', ' '], "_");
+        let host = part(&metrics.hostname);
+        let interval = self.interval.as_secs();
+        metrics
+            .named()
+            .iter()
+            .map(|(name, value)| {
+                let (base, instance) = match name.split_once('.') {
+                    Some((base, instance)) => (base, Some(instance)),
+                    None => (name.as_str(), None),
+                };
+                let (plugin, kind, type_instance) =
+                    Self::collectd_type(base).unwrap_or(("uptimed", "gauge", base));
+                let (plugin_instance, type_instance) = match (base, instance) {
+                    // alert.<metric> names the metric, not a device
+                    ("alert", Some(metric)) => (None, metric.replace('.', "-")),
+                    (_, instance) => (instance, type_instance.to_string()),
+                };
+                let plugin = match plugin_instance {
+                    Some(instance) => format!("{plugin}-{}", part(instance)),
+                    None => plugin.to_string(),
+                };
+                let kind = match type_instance.as_str() {
+                    "" => kind.to_string(),
+                    instance => format!("{kind}-{}", part(instance)),
+                };
+                format!("PUTVAL \"{host}/{plugin}/{kind}\" interval={interval} N:{value}\n")
+            })
+            .collect()
+    }
+
+    /// The Collectd plugin, type and type instance a metric is sent as
+    fn collectd_type(metric: &str) -> Option<(&'static str, &'static str, &'static str)> {
+        Some(match metric {
+            "up" => ("uptimed", "gauge", "up"),
+            "net-rx" => ("interface", "bytes", "rx"),
+            "net-tx" => ("interface", "bytes", "tx"),
+            "net-rx-err" => ("interface", "count", "rx_errors"),
+            "net-tx-err" => ("interface", "count", "tx_errors"),
+            "net-rx-drop" => ("interface", "count", "rx_dropped"),
+            "net-tx-drop" => ("interface", "count", "tx_dropped"),
+            "net-up" => ("interface", "gauge", "up"),
+            "net-speed" => ("interface", "gauge", "speed"),
+            "tcp-retrans" => ("tcp", "count", "retransmits"),
+            "conntrack" => ("conntrack", "conntrack", ""),
+            "conntrack-pct" => ("conntrack", "percent", "used"),
+            "uptime" => ("uptime", "uptime", ""),
+            "boottime" => ("uptime", "gauge", "boottime"),
+            "availmem" => ("memory", "percent", "available"),
+            "memused" => ("memory", "percent", "used"),
+            "availmem-bytes" => ("memory", "memory", "available"),
+            "membuffers" => ("memory", "percent", "buffered"),
+            "membuffers-bytes" => ("memory", "memory", "buffered"),
+            "memcached" => ("memory", "percent", "cached"),
+            "memcached-bytes" => ("memory", "memory", "cached"),
+            "memdirty" => ("memory", "percent", "dirty"),
+            "memdirty-bytes" => ("memory", "memory", "dirty"),
+            "memreclaimable" => ("memory", "percent", "slab_recl"),
+            "memreclaimable-bytes" => ("memory", "memory", "slab_recl"),
+            "swapused" => ("swap", "percent", "used"),
+            "diskfree" => ("df", "percent", "free"),
+            "diskused" => ("df", "percent", "used"),
+            "diskfree-bytes" => ("df", "df_complex", "free"),
+            "inodefree" => ("df", "percent", "inodes_free"),
+            "fs-readonly" => ("df", "gauge", "readonly"),
+            "disk-read" => ("disk", "bytes", "read"),
+            "disk-write" => ("disk", "bytes", "write"),
+            "load" => ("load", "percent", "shortterm"),
+            "load5" => ("load", "percent", "midterm"),
+            "load15" => ("load", "percent", "longterm"),
+            "procs" => ("processes", "count", "total"),
+            "procs-total" => ("processes", "count", "tasks"),
+            "procs-running" => ("processes", "count", "running"),
+            "zombies" => ("processes", "count", "zombies"),
+            "entropy" => ("entropy", "entropy", ""),
+            "kernel" => ("kernel", "gauge", "version"),
+            "fd-used" => ("fhcount", "count", "used"),
+            "fd-used-pct" => ("fhcount", "percent", "used"),
+            "cpu" => ("cpu", "percent", "active"),
+            "cpusteal" => ("cpu", "percent", "steal"),
+            "iowait" => ("cpu", "percent", "wait"),
+            "ctxt" => ("contextswitch", "contextswitch", ""),
+            "intr" => ("irq", "count", "total"),
+            "cputemp" => ("thermal", "temperature", ""),
+            "disktemp" => ("disk", "temperature", ""),
+            "psi-cpu" => ("pressure", "percent", "cpu"),
+            "psi-mem" => ("pressure", "percent", "memory"),
+            "psi-io" => ("pressure", "percent", "io"),
+            "alert" => ("alert", "gauge", ""),
+            "collect-ms" => ("uptimed", "gauge", "collect_ms"),
+            "send-lag" => ("uptimed", "gauge", "send_lag"),
+            "sends" => ("uptimed", "counter", "sends"),
+            _ => return None,
+        })
+    }
+
+    /// The inside of a JSON string
+    fn json_escape(s: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for c in s.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Renders the prefix template. The FQDN needs a resolver lookup so it's
+    /// only worked out when the template asks for it. Without a machine ID
+    /// its place says `unknown`.
+    ///
+    /// The dots written in the template and the namespace become
+    /// `--separator`, the ones in the hostname are part of it.
+    fn prefix(config: &Config, hostname: &str, machine_id: Option<&str>) -> String {
+        let host = |name: &str| match &config.sanitize_hostname {
+            Some(replacement) => Self::sanitize(name, replacement),
+            None => name
+                .chars()
+                .map(|c| match c {
+                    ':' | '|' => '_',
+                    c if c.is_whitespace() || c.is_control() => '_',
+                    c => c,
+                })
+                .collect(),
+        };
+        let mut prefix = config
+            .prefix_template
+            .replace("{namespace}", &config.namespace)
+            .replace('.', &config.separator)
+            .replace("{hostname}", &host(hostname))
+            .replace("{machine_id}", machine_id.unwrap_or("unknown"));
+        if prefix.contains("{fqdn}") {
+            prefix = prefix.replace("{fqdn}", &host(&SysInfo::fqdn(hostname)));
+        }
+        prefix
+    }
+
+    /// `web-01.prod.internal` becomes `web-01_prod_internal`, anything else
+    /// outside `[A-Za-z0-9_-]` is dropped
+    fn sanitize(hostname: &str, dot_replacement: &str) -> String {
+        let mut sanitized = String::with_capacity(hostname.len());
+        for c in hostname.chars() {
+            match c {
+                '.' => sanitized.push_str(dot_replacement),
+                c if c.is_ascii_alphanumeric() || c == '_' || c == '-' => sanitized.push(c),
+                _ => {}
+            }
+        }
+        sanitized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// The StatsD names, the part of each line before the value
+    fn names(statsd: &str) -> Vec<&str> {
+        statsd
+            .lines()
+            .map(|line| line.split(':').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn prefix_template() {
+        let prefix = |template: &str| {
+            let args = [
+                "--prefix-template",
+                template,
+                "localhost",
+                "myapp",
+                "/",
+                "lo",
+            ];
+            Encoder::prefix(&testing::config(&args), "web-01", Some("4b1e6c9f"))
+        };
+        assert_eq!(prefix("{namespace}.{hostname}"), "myapp.web-01");
+        assert_eq!(prefix("{hostname}"), "web-01");
+        assert_eq!(prefix("prod.eu-west.{hostname}"), "prod.eu-west.web-01");
+        assert_eq!(prefix("{namespace}.{machine_id}"), "myapp.4b1e6c9f");
+        let default = testing::config(&["localhost", "myapp", "/", "lo"]);
+        assert_eq!(Encoder::prefix(&default, "web-01", None), "myapp.web-01");
+        // A name that doesn't resolve is its own FQDN
+        let args = [
+            "--prefix-template",
+            "{fqdn}",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ];
+        let config = testing::config(&args);
+        assert_eq!(
+            Encoder::prefix(&config, "nonexistent.invalid", None),
+            "nonexistent.invalid"
+        );
+        let metrics = Metrics {
+            uptime: Some(3600.0),
+            ..Metrics::default()
+        };
+        let args = [
+            "--prefix-template",
+            "{hostname}",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ];
+        let statsd = Encoder::new(&testing::config(&args), "web-01").serialize(&metrics);
+        assert!(statsd.contains("\nweb-01.uptime:3600|g\n"), "{statsd}");
+        for invalid in ["{namespace}.{host}", "{namespace", "a:b", "a b"] {
+            let args = [
+                "--prefix-template",
+                invalid,
+                "localhost",
+                "myapp",
+                "/",
+                "lo",
+            ];
+            assert!(testing::try_config(&args).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn hostname_sanitized() {
+        let config = testing::config(&["localhost", "myapp", "/", "lo"]);
+        let encoder = Encoder::new(&config, "web:01|prod\nbox");
+        let metrics = Metrics {
+            uptime: Some(3600.0),
+            ..Metrics::default()
+        };
+        for line in encoder.serialize(&metrics).lines() {
+            assert!(line.starts_with("myapp.web_01_prod_box."), "{line}");
+            assert_eq!(line.matches([':', '|']).count(), 2, "{line}");
+        }
+    }
+
+    #[test]
+    fn tags() {
+        let metrics = Metrics {
+            uptime: Some(3600.0),
+            sends: 1,
+            ..Metrics::default()
+        };
+        let config = testing::config(&["--tags", "env:prod,web", "localhost", "myapp", "/", "lo"]);
+        let statsd = Encoder::new(&config, "web-01").serialize(&metrics);
+        assert!(
+            statsd.contains("myapp.web-01.uptime:3600|g|#env:prod,web\n"),
+            "{statsd}"
+        );
+        assert!(
+            statsd.contains("myapp.web-01.sends:1|c|#env:prod,web\n"),
+            "{statsd}"
+        );
+        // Plain StatsD without them
+        let config = testing::config(&["localhost", "myapp", "/", "lo"]);
+        let statsd = Encoder::new(&config, "web-01").serialize(&metrics);
+        assert!(statsd.contains("myapp.web-01.uptime:3600|g\n") && !statsd.contains('#'));
+        for malformed in ["env:prod,", ":prod", "env prod", "env|prod", "env#1"] {
+            let args = ["--tags", malformed, "localhost", "myapp", "/", "lo"];
+            assert!(testing::try_config(&args).is_err(), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn sanitize_hostname() {
+        let prefix = |options: &[&str], hostname: &str| {
+            let args = [options, &["localhost", "myapp", "/", "lo"]].concat();
+            Encoder::prefix(&testing::config(&args), hostname, None)
+        };
+        let sanitize = ["--sanitize-hostname"];
+        assert_eq!(
+            prefix(&sanitize, "web-01.prod.internal"),
+            "myapp.web-01_prod_internal"
+        );
+        assert_eq!(prefix(&sanitize, "Web_01@prod!"), "myapp.Web_01prod");
+        assert_eq!(prefix(&sanitize, "ünï.côdé"), "myapp.n_cd");
+        let dashes = ["--sanitize-hostname", "--dot-replacement", "-"];
+        assert_eq!(
+            prefix(&dashes, "web-01.prod.internal"),
+            "myapp.web-01-prod-internal"
+        );
+        let removed = ["--sanitize-hostname", "--dot-replacement", ""];
+        assert_eq!(prefix(&removed, "web-01.prod"), "myapp.web-01prod");
+        // Off by default, the dots stay
+        assert_eq!(
+            prefix(&[], "web-01.prod.internal"),
+            "myapp.web-01.prod.internal"
+        );
+        let args = ["--dot-replacement", "-", "localhost", "myapp", "/", "lo"];
+        assert!(testing::try_config(&args).is_err());
+        let args = [
+            "--sanitize-hostname",
+            "--dot-replacement",
+            ".",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ];
+        assert!(testing::try_config(&args).is_err());
+    }
+
+    #[test]
+    fn sample_rate() {
+        let config = testing::config(&["--sample-rate", "0.5", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        let encoder = Encoder::new(&config, info.hostname());
+        let metrics = info.sample();
+        assert!(encoder.due(&metrics));
+        for line in encoder.serialize(&metrics).lines() {
+            assert!(line.ends_with("|g|@0.5") || line.ends_with(":1|c|@0.5"));
+        }
+        assert!(!encoder.due(&info.sample()));
+        assert!(encoder.due(&info.sample()));
+
+        // Rounded to every 3rd, and the lines say the rate that's sent
+        let config = testing::config(&["--sample-rate", "0.3", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        let encoder = Encoder::new(&config, info.hostname());
+        let due: Vec<bool> = (0..4).map(|_| encoder.due(&info.sample())).collect();
+        assert_eq!(due, [true, false, false, true]);
+        let statsd = encoder.serialize(&info.sample());
+        assert!(
+            statsd
+                .lines()
+                .all(|line| line.ends_with("|@0.3333333333333333")),
+            "{statsd}"
+        );
+    }
+
+    #[test]
+    fn net_as_counter() {
+        let config = testing::config(&["--net-as-counter", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        let encoder = Encoder::new(&config, info.hostname());
+        let statsd = encoder.serialize(&info.sample());
+        assert!(
+            statsd.contains(".net-rx:0|c\n") && statsd.contains(".net-tx:0|c\n"),
+            "{statsd}"
+        );
+        for line in statsd.lines() {
+            let counter = [".net-rx:", ".net-tx:", ".sends:"]
+                .iter()
+                .any(|name| line.contains(name));
+            assert_eq!(line.ends_with("|c"), counter, "{line}");
+        }
+    }
+
+    #[test]
+    fn gauge_delta() {
+        let config = testing::config(&["--gauge-delta", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        let encoder = Encoder::new(&config, info.hostname());
+        let statsd = encoder.serialize(&info.sample());
+        for line in statsd.lines() {
+            let net = [".net-rx:", ".net-tx:"]
+                .iter()
+                .any(|name| line.contains(name));
+            assert_eq!(line.contains(":+"), net, "{line}");
+            if net {
+                let value = line.split(":+").nth(1).unwrap();
+                assert!(line.ends_with("|g") && value.starts_with(char::is_numeric));
+            }
+        }
+
+        let sys = TempDir::new("gauge-delta");
+        testing::counters(&sys, "eth0", 1000, 500);
+        let config = testing::config(&[
+            "--gauge-delta",
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "--metrics",
+            "net-rx,net-tx",
+            "localhost",
+            "myapp",
+            "/",
+            "eth0",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        let encoder = Encoder::new(&config, "web-01");
+        let mut sample = |rx, tx| {
+            testing::counters(&sys, "eth0", rx, tx);
+            info.refresh();
+            encoder.serialize(&info.sample())
+        };
+        assert_eq!(
+            sample(2500, 900),
+            "myapp.web-01.net-rx:+1500|g\nmyapp.web-01.net-tx:+400|g\n"
+        );
+        // The link was reset, and then tx wraps
+        assert_eq!(
+            sample(300, u64::MAX),
+            "myapp.web-01.net-rx:+0|g\nmyapp.web-01.net-tx:+18446744073709550715|g\n"
+        );
+        assert_eq!(
+            sample(800, 100),
+            "myapp.web-01.net-rx:+500|g\nmyapp.web-01.net-tx:+0|g\n"
+        );
+    }
+
+    #[test]
+    fn separator() {
+        let sys = TempDir::new("separator");
+        for interface in ["eth0.100", "eth1"] {
+            testing::counters(&sys, interface, 1000, 500);
+        }
+        let config = testing::config(&[
+            "--separator",
+            "_",
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "--metrics",
+            "uptime,net-rx",
+            "--alert",
+            "uptime<1",
+            "localhost",
+            "prod.myapp",
+            "/",
+            "eth0.100,eth1",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        let encoder = Encoder::new(&config, "web-01.internal");
+        let statsd = encoder.serialize(&info.sample());
+        // The dots in the hostname and the VLAN's name aren't separators
+        assert_eq!(
+            names(&statsd),
+            [
+                "prod_myapp_web-01.internal_net-rx_eth0.100",
+                "prod_myapp_web-01.internal_net-rx_eth1",
+                "prod_myapp_web-01.internal_uptime",
+                "prod_myapp_web-01.internal_alert_uptime",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_hostname() {
+        let sys = TempDir::new("no-hostname");
+        for interface in ["eth0", "eth1"] {
+            testing::counters(&sys, interface, 1000, 500);
+        }
+        let filesystems = format!("/,{}", std::env::temp_dir().display());
+        let config = testing::config(&[
+            "--no-hostname",
+            "--sys-root",
+            sys.path().to_str().unwrap(),
+            "--metrics",
+            "net-rx,diskfree",
+            "localhost",
+            "prod",
+            &filesystems,
+            "eth0,eth1",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        info.refresh();
+        let statsd = Encoder::new(&config, "web-01").serialize(&info.sample());
+        let names = names(&statsd);
+        assert!(names.contains(&"prod.net-rx.eth0"), "{names:?}");
+        assert!(names.contains(&"prod.net-rx.eth1"), "{names:?}");
+        assert!(names.contains(&"prod.diskfree.root"), "{names:?}");
+        assert!(
+            names.iter().all(|name| !name.contains("web-01")),
+            "{names:?}"
+        );
+    }
+
+    #[test]
+    fn influx() {
+        let config = testing::config(&["--format", "influx", "localhost", "my app", "/", "lo"]);
+        let encoder = Encoder::new(&config, "web-01");
+        let metrics = Metrics {
+            hostname: "web 01,eu".to_string(),
+            uptime: Some(3600.0),
+            avail_mem: Some(80.4),
+            sends: 1,
+            ..Metrics::default()
+        };
+        let influx = encoder.serialize_influx(&metrics);
+        let (point, timestamp) = influx.trim_end().rsplit_once(' ').unwrap();
+        assert_eq!(
+            point,
+            r"my\ app,host=web\ 01\,eu up=1i,uptime=3600i,availmem=80,memused=20,collect_ms=0i,sends=1i"
+        );
+        // Nanoseconds, well past the seconds since the epoch
+        assert!(timestamp.parse::<u128>().unwrap() > 1_000_000_000_000_000_000);
+        assert!(influx.ends_with('\n') && influx.lines().count() == 1);
+    }
+
+    #[test]
+    fn graphite() {
+        let config = testing::config(&["--format", "graphite", "localhost", "myapp", "/", "lo"]);
+        let encoder = Encoder::new(&config, "web-01");
+        let metrics = Metrics {
+            uptime: Some(3600.0),
+            avail_mem: Some(80.0),
+            sends: 1,
+            ..Metrics::default()
+        };
+        let graphite = encoder.serialize_graphite(&metrics);
+        let lines: Vec<Vec<&str>> = graphite
+            .lines()
+            .map(|line| line.split(' ').collect())
+            .collect();
+        assert_eq!(
+            lines.iter().map(|line| line[0]).collect::<Vec<_>>(),
+            [
+                "myapp.web-01.up",
+                "myapp.web-01.uptime",
+                "myapp.web-01.availmem",
+                "myapp.web-01.memused",
+                "myapp.web-01.collect-ms",
+                "myapp.web-01.sends",
+            ]
+        );
+        assert_eq!(lines[1][1], "3600");
+        // One timestamp for the whole sample, in seconds
+        let timestamp = lines[0][2];
+        assert!(
+            lines
+                .iter()
+                .all(|line| line.len() == 3 && line[2] == timestamp)
+        );
+        let now = unix_time().as_secs();
+        assert!(now - timestamp.parse::<u64>().unwrap() < 5);
+    }
+
+    #[test]
+    fn json() {
+        let config = testing::config(&["--format", "json", "localhost", "myapp", "/", "lo"]);
+        let encoder = Encoder::new(&config, "web-01");
+        let metrics = Metrics {
+            hostname: "web-01".to_string(),
+            uptime: Some(3600.0),
+            avail_mem: Some(80.0),
+            sends: 1,
+            ..Metrics::default()
+        };
+        let json = encoder.serialize_json(&metrics);
+        let (start, timestamp) = json.split_once(r#""timestamp":"#).unwrap();
+        assert_eq!(start, r#"{"host":"web-01","#);
+        let (timestamp, fields) = timestamp.split_once(',').unwrap();
+        assert!(timestamp.parse::<u64>().is_ok());
+        assert_eq!(
+            fields,
+            r#""up":1,"uptime":3600,"availmem":80.0,"memused":20.0,"collect_ms":0,"sends":1}"#
+                .to_string()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn otlp() {
+        let config = testing::config(&[
+            "--format",
+            "otlp",
+            "--endpoint",
+            "http://localhost:4318/v1/metrics",
+            "--namespace",
+            "myapp",
+            "--filesystem",
+            "/",
+            "--interface",
+            "lo",
+        ]);
+        let encoder = Encoder::new(&config, "web-01");
+        let metrics = Metrics {
+            hostname: "web-01".to_string(),
+            avail_mem: Some(80.0),
+            sends: 3,
+            ..Metrics::default()
+        };
+        let body = encoder.serialize_otlp(&metrics);
+        assert!(body.contains(r#"{"key":"host.name","value":{"stringValue":"web-01"}}"#));
+        assert!(
+            body.contains(r#"{"name":"myapp.availmem","gauge":{"dataPoints":[{"timeUnixNano":""#)
+        );
+        assert!(body.contains(r#""asDouble":80.0}]}}"#));
+        assert!(body.contains(
+            r#"{"name":"myapp.sends","sum":{"aggregationTemporality":2,"isMonotonic":true,"#
+        ));
+        assert!(body.contains(r#""asInt":"3"}]}}"#));
+    }
+
+    #[test]
+    fn collectd() {
+        let config = testing::config(&[
+            "--format",
+            "collectd",
+            "--interval",
+            "30",
+            "/run/collectd-unixsock",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let encoder = Encoder::new(&config, "web-01");
+        let metrics = Metrics {
+            hostname: "web-01".to_string(),
+            uptime: Some(3600.0),
+            avail_mem: Some(80.0),
+            load: Some([50.0, 25.0, 12.5]),
+            cpu_cores: vec![(0, 40.0)],
+            sends: 3,
+            ..Metrics::default()
+        };
+        assert_eq!(
+            encoder.serialize_collectd(&metrics),
+            "PUTVAL \"web-01/uptimed/gauge-up\" interval=30 N:1\n\
+             PUTVAL \"web-01/uptime/uptime\" interval=30 N:3600\n\
+             PUTVAL \"web-01/memory/percent-available\" interval=30 N:80\n\
+             PUTVAL \"web-01/memory/percent-used\" interval=30 N:20\n\
+             PUTVAL \"web-01/load/percent-shortterm\" interval=30 N:50\n\
+             PUTVAL \"web-01/load/percent-midterm\" interval=30 N:25\n\
+             PUTVAL \"web-01/load/percent-longterm\" interval=30 N:13\n\
+             PUTVAL \"web-01/cpu-0/percent-active\" interval=30 N:40\n\
+             PUTVAL \"web-01/uptimed/gauge-collect_ms\" interval=30 N:0\n\
+             PUTVAL \"web-01/uptimed/counter-sends\" interval=30 N:3\n"
+        );
+    }
+}
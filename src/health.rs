@@ -0,0 +1,130 @@
+use crate::send::TCP_TIMEOUT;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// When a sample was last collected and sent, shared between the send loop
+/// and the `--health-addr` listener. Healthy means the last send was less
+/// than two intervals ago, so a send loop that's stuck or failing every time
+/// stops answering 200 and the supervisor can restart it.
+///
+/// ```
+/// use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, thread, time::Duration};
+/// use uptimed::Health;
+///
+/// let health = Health::new(Duration::from_secs(10));
+/// let listener = TcpListener::bind("127.0.0.1:0")?;
+/// let address = listener.local_addr()?;
+/// let server = health.clone();
+/// thread::spawn(move || server.serve(listener));
+/// let probe = || -> std::io::Result<String> {
+///     let mut stream = TcpStream::connect(address)?;
+///     stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+///     let mut response = String::new();
+///     stream.read_to_string(&mut response)?;
+///     Ok(response)
+/// };
+/// // Nothing sent yet
+/// assert!(probe()?.starts_with("HTTP/1.1 503 "));
+/// health.sent();
+/// assert!(probe()?.starts_with("HTTP/1.1 200 OK\r\n"));
+/// // Two intervals without a send
+/// health.set_interval(Duration::from_millis(50));
+/// thread::sleep(Duration::from_millis(150));
+/// assert!(probe()?.starts_with("HTTP/1.1 503 "));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct Health {
+    start: Instant,
+    /// Milliseconds from `start` to the last send plus one, 0 before the
+    /// first
+    last_sent: Arc<AtomicU64>,
+    interval_ms: Arc<AtomicU64>,
+}
+
+impl Health {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            last_sent: Arc::new(AtomicU64::new(0)),
+            interval_ms: Arc::new(AtomicU64::new(interval.as_millis() as u64)),
+        }
+    }
+
+    /// For a reload that changed `--interval`
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval_ms
+            .store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Records a sample collected and sent just now
+    pub fn sent(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last_sent.store(now + 1, Ordering::Relaxed);
+    }
+
+    /// How long ago the last sample was sent, `None` before the first
+    pub fn since_sent(&self) -> Option<Duration> {
+        let last_sent = self.last_sent.load(Ordering::Relaxed).checked_sub(1)?;
+        let now = self.start.elapsed().as_millis() as u64;
+        Some(Duration::from_millis(now.saturating_sub(last_sent)))
+    }
+
+    pub fn healthy(&self) -> bool {
+        let limit = Duration::from_millis(self.interval_ms.load(Ordering::Relaxed) * 2);
+        self.since_sent().is_some_and(|since| since < limit)
+    }
+
+    /// Answers every request on `listener` with 200 or 503, whatever its
+    /// method and path. Runs until the process exits, on its own thread.
+    pub fn serve(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Unable to accept health check connection: {err}");
+                    continue;
+                }
+            };
+            let _ = stream.set_read_timeout(Some(TCP_TIMEOUT));
+            let _ = stream.set_write_timeout(Some(TCP_TIMEOUT));
+            // Only the end of the headers matters
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader
+                .read_line(&mut line)
+                .is_ok_and(|n| n > 0 && line.trim() != "")
+            {
+                line.clear();
+            }
+            let (status, body) = match (self.healthy(), self.since_sent()) {
+                (true, _) => ("200 OK", "OK\n".to_string()),
+                (false, Some(since)) => (
+                    "503 Service Unavailable",
+                    format!("Last sample sent {}s ago\n", since.as_secs()),
+                ),
+                (false, None) => (
+                    "503 Service Unavailable",
+                    "No sample sent yet\n".to_string(),
+                ),
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\n\
+                 Content-Type: text/plain\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(err) = stream.write_all(response.as_bytes()) {
+                warn!("Unable to answer health check: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+/// Random delays up to `max`, so hosts started together don't all send in
+/// the same second. SplitMix64 is plenty for spreading start times and needs
+/// no dependency.
+/// <https://prng.di.unimi.it/splitmix64.c>
+///
+/// ```
+/// use std::time::Duration;
+/// use uptimed::Jitter;
+///
+/// let max = Duration::from_secs(30);
+/// let mut jitter = Jitter::new(max, 42);
+/// let mut again = Jitter::new(max, 42);
+/// for _ in 0..100 {
+///     let delay = jitter.next_delay();
+///     assert!(delay <= max);
+///     assert_eq!(delay, again.next_delay());
+/// }
+/// assert_ne!(Jitter::seed("web-01", 1), Jitter::seed("web-02", 1));
+/// ```
+pub struct Jitter {
+    max: Duration,
+    state: u64,
+}
+
+impl Jitter {
+    pub fn new(max: Duration, seed: u64) -> Self {
+        Self { max, state: seed }
+    }
+
+    /// Different for every host, and for every run on the same host
+    pub fn seed(hostname: &str, pid: u32) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hostname.hash(&mut hasher);
+        pid.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        // The top 53 bits make an evenly spread f64 in [0, 1)
+        let fraction = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        self.max.mul_f64(fraction)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Samples are due one interval after the previous one was due, not after
+/// it finished, so time spent collecting and sending doesn't push the
+/// schedule back. Slots missed by an overrun (a send stuck on a timeout, a
+/// suspended machine) are skipped rather than sent back to back.
+pub fn next_deadline(due: Instant, interval: Duration, now: Instant) -> Instant {
+    let next = due + interval;
+    if next > now {
+        return next;
+    }
+    let missed = (now - next).as_nanos() / interval.as_nanos() + 1;
+    warn!("Sampling fell behind, skipping {missed} interval(s)");
+    next + interval * missed as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_drift() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        // Collecting and sending took a second, the next sample is still
+        // due on the minute
+        let due = next_deadline(start, interval, start + Duration::from_secs(1));
+        assert_eq!(due, start + interval);
+        let due = next_deadline(due, interval, due + Duration::from_millis(59_999));
+        assert_eq!(due, start + interval * 2);
+    }
+
+    #[test]
+    fn missed_ticks() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        // Stuck for 185s, the slots at 60, 120 and 180 are gone
+        let due = next_deadline(start, interval, start + Duration::from_secs(185));
+        assert_eq!(due, start + interval * 4);
+        // Finishing right on the next slot misses it too
+        let due = next_deadline(start, interval, start + interval);
+        assert_eq!(due, start + interval * 2);
+    }
+}
@@ -0,0 +1,50 @@
+//! Collects system health from /proc, /sys and statvfs and pushes it to
+//! StatsD, InfluxDB or Graphite, or serves it to Prometheus. The `uptimed`
+//! binary is a thin wrapper around this.
+//!
+//! ```
+//! let args: Vec<String> = ["localhost", "myapp", "/", "lo"]
+//!     .iter()
+//!     .map(|arg| arg.to_string())
+//!     .collect();
+//! let config = uptimed::Config::from_args(&args)?;
+//! let mut info = uptimed::SysInfo::new(&config);
+//! let metrics = info.collect();
+//! assert!(metrics.avail_mem.is_some_and(|avail_mem| avail_mem <= 100.0));
+//! for (name, value) in metrics.named() {
+//!     println!("{name} = {value}");
+//! }
+//! # Ok::<(), String>(())
+//! ```
+
+#[macro_use]
+mod logging;
+mod collect;
+mod collectors;
+mod config;
+mod format;
+mod health;
+mod jitter;
+mod metrics;
+mod prometheus;
+mod send;
+mod sink;
+mod sysinfo;
+#[cfg(test)]
+mod testing;
+
+pub use collect::{
+    CollectError, CpuTimes, FileNr, FsStats, LoadAvg, MemInfo, Mount, TcpStats, set_roots,
+};
+pub use config::{Alert, Config, Endpoint, Format, List, Protocol, Syslog};
+pub use format::Encoder;
+pub use health::Health;
+pub use jitter::{Jitter, next_deadline};
+#[doc(hidden)]
+pub use logging::log;
+pub use logging::{Level, set_max_level};
+pub use metrics::{DiskMetrics, FilesystemMetrics, InterfaceMetrics, Metrics, Value};
+pub use prometheus::serve_prometheus;
+pub use send::NetworkSink;
+pub use sink::{MetricSink, Stdout};
+pub use sysinfo::SysInfo;
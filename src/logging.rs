@@ -0,0 +1,101 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Log levels, most severe first
+#[derive(Clone, Copy)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// Warnings and errors by default, each -v adds a level
+    pub fn from_verbosity(verbosity: u8) -> Self {
+        match verbosity {
+            0 => Level::Warn,
+            1 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+/// Messages less severe than this are dropped
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Used by the logging macros
+#[doc(hidden)]
+pub fn log(level: Level, message: fmt::Arguments) {
+    if level as u8 <= MAX_LEVEL.load(Ordering::Relaxed) {
+        eprintln!("{}: {message}", level.label());
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log($crate::Level::Error, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log($crate::Level::Warn, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log($crate::Level::Info, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log($crate::Level::Debug, format_args!($($arg)*)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn verbosity() {
+        let level = |args: &[&str]| {
+            let args = [args, &["localhost", "myapp", "/", "lo"]].concat();
+            Level::from_verbosity(testing::config(&args).verbosity) as u8
+        };
+        assert_eq!(level(&[]), Level::Warn as u8);
+        assert_eq!(level(&["-v"]), Level::Info as u8);
+        assert_eq!(level(&["-vv"]), Level::Debug as u8);
+        assert_eq!(level(&["-v", "-v"]), Level::Debug as u8);
+        // There's nothing past debug
+        assert_eq!(level(&["-vvvv"]), Level::Debug as u8);
+        assert_eq!(
+            testing::config(&["-v", "-vv", "localhost", "myapp", "/", "lo"]).verbosity,
+            3
+        );
+        // Only -v sets it, there's no option or variable behind it
+        let args = ["--verbose", "2", "localhost", "myapp", "/", "lo"];
+        assert_eq!(
+            testing::try_config(&args).err().as_deref(),
+            Some("Unknown option verbose")
+        );
+        let vars = [("UPTIMED_VERBOSE".to_string(), "2".to_string())];
+        let args = testing::args(&["localhost", "myapp", "/", "lo"]);
+        assert!(crate::Config::from_sources(&args, vars).is_err());
+    }
+}
@@ -1,191 +1,339 @@
-use std::{mem, fs, ffi::CString, net::UdpSocket, thread, time::Duration};
 use libc::{self};
+use std::{
+    mem,
+    net::TcpListener,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{self, UnixDatagram},
+    },
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use uptimed::{
+    Config, Encoder, Health, Jitter, Level, List, MetricSink, Mount, NetworkSink, Stdout, SysInfo,
+    error, info, next_deadline, serve_prometheus, set_max_level, set_roots, warn,
+};
 
-struct SysInfo {
-    namespace: String,
-    destination: String,
-    interface: String,
-    filesystem: String,
-    hostname: String,
-    last_seen_net_rx: u64,
-    last_seen_net_tx: u64,
-    net_rx: u64,
-    net_tx: u64,
-    uptime: f32,
-    avail_mem: f64,
-    load: f32,
-    disk_free: f64,
+/// Set from the SIGTERM/SIGINT handler, the send loop exits once it sees it
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Set from the SIGHUP handler, the send loop reloads the configuration
+/// before its next sample
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// How often a sleeping send loop checks for a shutdown request
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+const USAGE: &str = "Usage: uptimed [-v|-vv] [options] statsd-server[:port]|/socket/path[,...] namespace filesystem[,...] network-interface[,...] \n\
+     \x20      uptimed [options] --destination d --namespace n --filesystem f --interface i \n\
+     \x20      uptimed [options] --config file \n\
+     \n\
+     --destination d[,...]   StatsD server[:port] or socket path, the first argument. IPv6 \n\
+     \x20                       addresses take a port in brackets, [::1]:8125 \n\
+     --namespace n           First part of every metric path, the second argument \n\
+     --filesystem f[,...]    Filesystems to report diskfree for, the third argument. auto \n\
+     \x20                       is every mounted filesystem but proc, tmpfs, cgroup, overlay \n\
+     \x20                       and the other pseudo filesystems \n\
+     --include-fs-types t[,...] \n\
+     \x20                       Filesystem types auto keeps even so, e.g. tmpfs \n\
+     --exclude-fs-types t[,...] \n\
+     \x20                       Filesystem types auto skips as well, e.g. nfs4 \n\
+     --interface i[,...]     Network interfaces to report net-rx/net-tx for, the fourth \n\
+     \x20                       argument. auto is every interface but loopback \n\
+     --all-interfaces        Send one net-rx and net-tx summed over the interfaces rather \n\
+     \x20                       than one per interface \n\
+     -h, --help              Print this and exit \n\
+     --version               Print the version and exit \n\
+     --list-interfaces       Print the network interfaces with their state and byte \n\
+     \x20                       counters, and exit \n\
+     --list-filesystems      Print the mounted filesystems and their types, and exit \n\
+     -v, -vv                 Log sends and scrapes to stderr at info or debug level, by \n\
+     \x20                       default only warnings and errors are logged \n\
+     --port port             StatsD port, when not given with the server (default 8125) \n\
+     --disks dev[,...]       Block devices (e.g. sda,nvme0n1) to report disk-read.<dev> \n\
+     \x20                       and disk-write.<dev> bytes per interval for \n\
+     --disable name[,...]    Collectors to turn off, their metrics aren't sent: net, \n\
+     \x20                       net-errors, link, tcp, conntrack, uptime, boottime, \n\
+     \x20                       availmem, membreakdown, swapused, diskfree, disk-io, load, \n\
+     \x20                       procs, entropy, kernel, fd, cpu, cputemp, disktemp, psi \n\
+     --enable name[,...]     Collectors that are off by default to turn on: zombies, \n\
+     \x20                       which reads every process's /proc/<pid>/stat \n\
+     --metrics name[,...]    Only send these of the metrics listed below, e.g. \n\
+     \x20                       net-rx,net-tx,uptime. Collectors with nothing to send are off \n\
+     --disable-metrics name[,...] \n\
+     \x20                       Send every metric but these \n\
+     --thermal-zone n        Report cputemp from /sys/class/thermal/thermal_zone<n> rather \n\
+     \x20                       than the hottest zone \n\
+     --interval seconds      Seconds between samples, at least 1 (default 60) \n\
+     --count n               Exit after taking n samples, sent or not (default 0, never), \n\
+     \x20                       staying in the foreground, e.g. --interval 1 --count 5 in CI \n\
+     --jitter seconds        Wait a random time up to this long before the first sample, \n\
+     \x20                       so hosts started together don't send together (default 0). \n\
+     \x20                       Later samples keep to the delayed schedule. uptimed goes \n\
+     \x20                       into the background before waiting, so the first sample's \n\
+     \x20                       errors are only logged \n\
+     --resolve-ttl seconds   Seconds a looked up server address is reused (default 300), \n\
+     \x20                       it's also looked up again after a failed send \n\
+     --buffer-size n         Samples to keep per server while it can't be reached and send \n\
+     \x20                       once it's back (default 0, unsent samples are dropped). \n\
+     \x20                       They get one last try on exit \n\
+     --send-retries n        Times to try a failed send again before the next interval, \n\
+     \x20                       waiting 0.25s and doubling up to 4s between tries \n\
+     \x20                       (default 0) \n\
+     --max-packet-size n     Largest UDP or Unix datagram in bytes (default 1400), bigger \n\
+     \x20                       samples are split between lines \n\
+     --bind ip               Send UDP from this local address, e.g. the metrics VLAN's on \n\
+     \x20                       a multi-homed host, rather than the one routing picks \n\
+     --protocol udp|tcp      Transport to the server (default udp), TCP reconnects on failure. \n\
+     \x20                       A server starting with / is a Unix datagram socket path. \n\
+     \x20                       With several servers each gets every sample \n\
+     --syslog                Also write each sample to syslog, a message per line. The \n\
+     \x20                       destination can then be left out if the namespace, \n\
+     \x20                       filesystem and interface are given as options \n\
+     --syslog-socket path    The log daemon's socket (default /dev/log) \n\
+     --syslog-facility name  daemon (default), user, local0 to local7 or another \n\
+     \x20                       syslog(3) facility \n\
+     --syslog-tag tag        Identifies the messages (default uptimed) \n\
+     --output-file path      Also append each sample to path under a # <unix time> line, \n\
+     \x20                       reopening it when it's rotated. The destination can be \n\
+     \x20                       left out like with --syslog \n\
+     --format name           Wire format: statsd (default), influx (InfluxDB line protocol), \n\
+     \x20                       graphite (Graphite plaintext), json (one object per \n\
+     \x20                       sample, e.g. for jq with --once), otlp (OpenTelemetry \n\
+     \x20                       OTLP/HTTP JSON, sent to --endpoint) or collectd (PUTVAL \n\
+     \x20                       commands for the unixsock plugin, the destination is \n\
+     \x20                       its socket path) \n\
+     --endpoint url          The http:// URL an OTLP collector takes metrics on, e.g. \n\
+     \x20                       http://collector:4318/v1/metrics. The destination is left \n\
+     \x20                       out, give the other arguments as options \n\
+     --precision digits      Decimal places for percentages, load and cpu (default 0, \n\
+     \x20                       whole numbers), up to 6 \n\
+     --absolute              Send availmem, the memory breakdown and diskfree as bytes, \n\
+     \x20                       named availmem-bytes, memcached-bytes, diskfree-bytes and so \n\
+     \x20                       on, rather than percent \n\
+     --alert m<n|m>n[,...]   Also send alert.<metric> as 1 while a metric is below or above \n\
+     \x20                       a threshold and 0 otherwise, e.g. 'availmem<20,load>100' \n\
+     --tags tag[,...]        DogStatsD tags added to every StatsD line, e.g. env:prod,web \n\
+     --machine-id-tag        Add a machine_id:<id> tag too, which outlives hostname changes \n\
+     --net-as-counter        Send net-rx and net-tx as StatsD counters (|c) of the bytes \n\
+     \x20                       in each interval, so the server reports them as a rate \n\
+     \x20                       per second and sums them across hosts. A flush with no \n\
+     \x20                       sample shows 0 rather than the last value \n\
+     --gauge-delta           Send net-rx and net-tx as StatsD gauge changes (:+<bytes>|g), \n\
+     \x20                       so the server's gauge adds up the bytes since it started \n\
+     --separator s           What the dots between the parts of StatsD names become, e.g. \n\
+     \x20                       _ or / for backends that don't nest on dots (default .). \n\
+     \x20                       Dots in the hostname and device names are kept \n\
+     --sample-rate r         Send StatsD every 1/r samples, e.g. 0.5 for every other one, \n\
+     \x20                       marking each line |@r so counters are scaled back up. \n\
+     \x20                       1/r is rounded to a whole number and r sent to match \n\
+     --prefix-template t     Metric path before each name (default {namespace}.{hostname}), \n\
+     \x20                       can use {namespace}, {hostname}, {fqdn} and {machine_id}, \n\
+     \x20                       the last from /etc/machine-id or /var/lib/dbus/machine-id \n\
+     \x20                       and unknown if neither has one \n\
+     --no-hostname           Leave the hostname out of the prefix, which is then just the \n\
+     \x20                       namespace, for servers that add it from the source address \n\
+     --local-hostname        Use our own hostname from gethostname(2) rather than the one \n\
+     \x20                       in /proc/sys/kernel/hostname, which under --proc-root is \n\
+     \x20                       the host's \n\
+     --fqdn                  Use the fully qualified hostname in the prefix, tags and \n\
+     \x20                       labels, falling back to the short one if it doesn't resolve \n\
+     --sanitize-hostname     Replace dots in the hostname part of the prefix and drop \n\
+     \x20                       anything but letters, digits, _ and - \n\
+     --dot-replacement s     What dots become with --sanitize-hostname (default _) \n\
+     --proc-root dir         Read /proc from dir (default /proc), e.g. the host's /proc \n\
+     \x20                       mounted at /host/proc in a container \n\
+     --sys-root dir          Read /sys from dir (default /sys) \n\
+     --config file           Read options from a TOML file of name = value lines using the \n\
+     \x20                       long option names, lists as arrays. Command line options win. \n\
+     \x20                       Reloaded on SIGHUP \n\
+     --once                  Print one sample to stdout in the chosen format and exit. \n\
+     \x20                       net-rx, net-tx and cpu only cover the moment since startup \n\
+     --prometheus addr       Serve /metrics on addr (e.g. 0.0.0.0:9100) instead of sending, \n\
+     \x20                       collecting on each scrape \n\
+     --health-addr addr      Answer any HTTP request on addr (e.g. 127.0.0.1:9101) with \n\
+     \x20                       200 if a sample was sent in the last two intervals and 503 \n\
+     \x20                       if not, for liveness probes \n\
+     \n\
+     Every option can also be set in the environment as UPTIMED_<NAME>, e.g. \n\
+     UPTIMED_RESOLVE_TTL=60. The command line wins over the environment, which wins \n\
+     over the config file. \n\
+     \n\
+     Stats are pulled from the /proc filesystem \n\
+     See https://www.kernel.org/doc/html/latest/filesystems/proc.html \n\
+     \n\
+     The following stats are emitted at startup and then once per interval, and sent to the\n\
+     StatsD host listed above. net-rx, net-tx and cpu are 0 in the startup sample.\n\n\
+     - hostname  /proc/sys/kernel/hostname \n\
+     - up        Always 1 and always first, whether or not anything else could \n\
+     \x20           be read. Alert if not seen in the last 5 minutes \n\
+     - net-rx    Bytes received in the last interval. With several interfaces \n\
+     \x20           there is one net-rx.<interface> per interface \n\
+     - net-tx    Bytes transmitted in the last interval, named like net-rx \n\
+     - net-rx-err, net-tx-err \n\
+     \x20           Receive and transmit errors in the last interval, named like \n\
+     \x20           net-rx. Often cabling or driver trouble \n\
+     - net-rx-drop, net-tx-drop \n\
+     \x20           Packets dropped in the last interval, named like net-rx \n\
+     - net-up    1 while the interface's link is up, 0 when it's down, named like \n\
+     \x20           net-rx \n\
+     - net-speed Link speed in Mbps, named like net-rx. Not sent for virtual \n\
+     \x20           interfaces, which have none \n\
+     - tcp-retrans TCP segments retransmitted in the last interval, a sign of \n\
+     \x20           packet loss on the way to peers \n\
+     - conntrack Connections in the netfilter tracking table. Not sent without \n\
+     \x20           nf_conntrack loaded \n\
+     - conntrack-pct Percent of nf_conntrack_max in use, new connections are \n\
+     \x20           dropped at 100 \n\
+     - uptime    Seconds of uptime \n\
+     - boottime  Unix time of boot. A change means the host rebooted, even if \n\
+     \x20           samples were missed around it \n\
+     - availmem  Percent of memory available alert if < 20 \n\
+     - memused   100 - availmem, for alerts written as memused > 80 \n\
+     - membuffers, memcached, memdirty, memreclaimable \n\
+     \x20           Percent of memory in Buffers, Cached, Dirty and SReclaimable, \n\
+     \x20           telling cache apart from real memory pressure \n\
+     - swapused  Percent of swap in use, 0 without swap \n\
+     - diskfree  Percent of disk free alert if less than < 10. With several filesystems \n\
+     \x20           there is one diskfree.<mount> per filesystem, / becomes root and \n\
+     \x20           /var/lib becomes var-lib \n\
+     - diskused  100 - diskfree, named like diskfree. Percent even with \n\
+     \x20           --absolute \n\
+     - inodefree Percent of inodes free, named like diskfree. Alert if < 10 \n\
+     - fs-readonly 1 if the filesystem is mounted read-only, as the kernel does \n\
+     \x20           after a disk error, 0 if not. Named like diskfree, alert if 1 \n\
+     - disk-read Bytes read from each --disks device in the last interval, as \n\
+     \x20           disk-read.<dev> \n\
+     - disk-write Bytes written, named like disk-read \n\
+     - load      Load average, scaled 100x (to get an int) and divided by the number \n\
+     \x20           of cores. 100 is generally saturation. Alert if > 100 \n\
+     - load5     The 5 minute load average, scaled like load \n\
+     - load15    The 15 minute load average, scaled like load \n\
+     - procs     Processes and threads, from /proc/loadavg \n\
+     - procs-running Those runnable right now, from the r/t field of \n\
+     \x20           /proc/loadavg, and procs-total the t. Both 0 if it's missing \n\
+     - zombies   Processes exited but not reaped by their parent, with \n\
+     \x20           --enable zombies. A count that keeps growing is a parent \n\
+     \x20           that's stopped calling wait(2) \n\
+     - entropy   Bits in the kernel's entropy pool. Before Linux 5.18 a low pool \n\
+     \x20           stalls TLS and SSH handshakes, alert if < 200 \n\
+     - kernel    The kernel release from /proc/version as one number, major * \n\
+     \x20           1000000 + minor * 1000 + patch, so 6.1.0-18-amd64 is 6001000 \n\
+     \x20           and hosts older than 6.1 are kernel < 6001000. The exporter \n\
+     \x20           has the release itself as node_uname_info{release} \n\
+     - fd-used   File handles open across the system, from /proc/sys/fs/file-nr \n\
+     - fd-used-pct Percent of fs.file-max open. Alert if > 90 \n\
+     - cpu       Percent of CPU time spent busy in the last interval, and as \n\
+     \x20           cpu.<n> for each core \n\
+     - cpusteal  Percent of CPU time the hypervisor gave to other guests in the \n\
+     \x20           last interval, 0 on bare metal. The noisy neighbour metric \n\
+     - iowait    Percent of CPU time spent idle with disk I/O outstanding in \n\
+     \x20           the last interval. High iowait with a low load points at \n\
+     \x20           storage \n\
+     - ctxt      Context switches in the last interval \n\
+     - intr      Interrupts serviced in the last interval \n\
+     - cputemp   Degrees C of the hottest thermal zone, or hwmon sensor. Not \n\
+     \x20           sent without sensors, as in most VMs \n\
+     - disktemp  Degrees C of each NVMe drive, and SATA or SAS drive with the \n\
+     \x20           drivetemp module loaded, as disktemp.<dev> from hwmon \n\
+     - psi-cpu   Percent of the last 10s some task waited for CPU, from \n\
+     \x20           /proc/pressure. Not sent on kernels without PSI \n\
+     - psi-mem   The same for memory \n\
+     - psi-io    The same for I/O \n\
+     - collect-ms Milliseconds the last collection took \n\
+     - send-lag  Seconds since the last sample that was sent without an error, \n\
+     \x20           normally the interval. More means samples were lost on our \n\
+     \x20           side rather than the network's \n\
+     - sends     Samples sent since startup, a StatsD counter of 1 per sample \n\n";
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGHUP => RELOAD.store(true, Ordering::Relaxed),
+        _ => SHUTDOWN.store(true, Ordering::Relaxed),
+    }
 }
 
-impl SysInfo {
-    pub fn new(
-        destination: String,
-        namespace: String,
-        filesystem: String,
-        interface: String,
-    ) -> Self {
-        Self {
-            namespace,
-            destination,
-            hostname: Self::get_hostname(),
-            last_seen_net_rx: Self::net_stats(&interface, "r"),
-            last_seen_net_tx: Self::net_stats(&interface, "t"),
-            net_rx: 0u64,
-            net_tx: 0u64,
-            interface,
-            uptime: Self::uptime(),
-            avail_mem: Self::avail_mem(),
-            load: Self::load(),
-            disk_free: Self::disk_free(&filesystem),
-            filesystem,
-        }
-    }
-
-    fn refresh(&mut self) {
-        let new_net_rx = Self::net_stats(&self.interface, "r");
-        let new_net_tx = Self::net_stats(&self.interface, "t");
-        self.net_rx = new_net_rx - self.last_seen_net_rx;
-        self.net_tx = new_net_tx - self.last_seen_net_tx;
-        self.last_seen_net_rx = new_net_rx;
-        self.last_seen_net_tx = new_net_tx;
-        self.uptime = Self::uptime();
-        self.avail_mem = Self::avail_mem();
-        self.load = Self::load();
-        self.disk_free = Self::disk_free(&self.filesystem);
-    }
-
-    fn get_hostname() -> String {
-        let hostname =
-            fs::read_to_string("/proc/sys/kernel/hostname").expect("Unable to read hostname");
-        hostname.trim().to_string()
-    }
-
-    fn net_stats(interface: &str, kind: &str) -> u64 {
-        fs::read_to_string(format! {"/sys/class/net/{interface}/statistics/{kind}x_bytes"})
-            .expect("Unable to read statistics from provided network interface")
-            .trim()
-            .parse()
-            .unwrap_or(0)
-    }
-
-    fn uptime() -> f32 {
-        fs::read_to_string("/proc/uptime")
-            .expect("Unable to read /proc/uptime")
-            .trim()
-            .split(" ")
-            .next()
-            .unwrap_or("0.0")
-            .parse()
-            .unwrap_or(0f32)
-            .round()
-    }
-
-    fn avail_mem() -> f64 {
-        let candidates: Vec<f64> = fs::read_to_string("/proc/meminfo")
-            .expect("Unable to read /proc/meminfo")
-            .lines()
-            .filter(|l| l.starts_with("MemTotal") || l.starts_with("MemAvailable"))
-            .map(|s| {
-                s.split(":")
-                    .last()
-                    .unwrap()
-                    .trim()
-                    .split(" ")
-                    .next()
-                    .unwrap()
-                    .parse()
-                    .unwrap()
-            })
-            .collect();
-        let total = candidates[0];
-        let avail = candidates[1];
-        (avail / total * 100.0).round()
-    }
-
-    fn load() -> f32 {
-        let load_avg: f32 = fs::read_to_string("/proc/loadavg")
-            .expect("Unable to read /proc/loadavg")
-            .trim()
-            .split(" ")
-            .next()
-            .unwrap()
-            .parse()
-            .unwrap();
-
-        let cores: f32 = fs::read_to_string("/proc/cpuinfo")
-            .expect("Unable to read /proc/cpuinfo")
-            .lines()
-            .filter(|l| l.starts_with("processor"))
-            .count()
-            .to_string()
-            .parse()
-            .unwrap();
-
-        (load_avg * 100f32 / cores).round()
-    }
-
-    fn disk_free(filesystem: &str) -> f64 {
-        let path = CString::new(filesystem).expect("Invalid filesystem path");
-        let mut stat = mem::MaybeUninit::<libc::statvfs>::uninit();
-        unsafe {
-            let res = libc::statvfs(path.as_ptr(), stat.as_mut_ptr());
-            if res != 0 {
-                println!("Cannot access filesystem stats, errno {}", res);
-                return 0f64
+fn install_signal_handlers() {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                panic!("Unable to install signal handler!");
             }
-            let statvfs = stat.assume_init();
-            (statvfs.f_bavail as f64 / statvfs.f_blocks as f64 * 100f64).round()
-        }
-    }
-
-    /// Format metrics for statsd
-    /// <https://github.com/statsd/statsd/blob/master/docs/metric_types.md>
-    /// Everything we report is a gauge
-    fn serialize(&self) -> String {
-        let prefix = format!("{}.{}", self.namespace, self.hostname);
-        format!(
-            "{}\n{}\n{}\n{}\n{}\n{}\n",
-            format!("{}.net-rx:{}|g", prefix, self.net_rx),
-            format!("{}.net-tx:{}|g", prefix, self.net_tx),
-            format!("{}.uptime:{}|g", prefix, self.uptime),
-            format!("{}.availmem:{}|g", prefix, self.avail_mem),
-            format!("{}.diskfree:{}|g", prefix, self.disk_free),
-            format!("{}.load:{}|g", prefix, self.load),
-        )
-    }
-
-    fn send(&mut self) {
-        self.refresh();
-        let socket = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to address");
-        socket
-            .send_to(
-                self.serialize().as_bytes(),
-                format!("{}:8125", self.destination),
-            )
-            .expect("couldn't send data");
+        }
     }
 }
 
-fn usage() {
-    println!(
-        "Usage: uptimed statsd-server namespace filesystem network-interface \n\
-         \n\
-         Stats are pulled from the /proc filesystem \n\
-         See https://www.kernel.org/doc/html/latest/filesystems/proc.html \n\
-         \n\
-         The following stats are emitted once per minute and sent to the StatsD host listed above\n\n\
-         - hostname  /proc/sys/kernel/hostname \n\
-         - net-rx    Bytes received in the last minute \n\
-         - net-tx    Bytes transmitted in the last minute \n\
-         - uptime    Seconds of uptime. Alert if not seen in the last 5 minutes \n\
-         - availmem  Percent of memory available alert if < 20 \n\
-         - diskfree  Percent of disk free alert if less than < 10 \n\
-         - load      Load average, scaled 100x (to get an int) and divided by the number
-            of cores. 100 is generally saturation. Alert if > 100 \n\n"
-    );
+/// Sleeps until `deadline`, waking early when shutdown is requested.
+/// Returns false if we should stop.
+fn sleep_until(deadline: Instant) -> bool {
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        thread::sleep(remaining.min(SHUTDOWN_POLL));
+    }
+    false
+}
+
+/// Tells systemd how we're doing over $NOTIFY_SOCKET, does nothing when we
+/// aren't a `Type=notify` service
+/// <https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html>
+fn sd_notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        // A leading @ means a socket in the abstract namespace
+        match path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => socket.send_to_addr(
+                state.as_bytes(),
+                &net::SocketAddr::from_abstract_name(name)?,
+            ),
+            None => socket.send_to(state.as_bytes(), &path),
+        }
+    });
+    if let Err(err) = sent {
+        warn!("Unable to notify systemd: {err}");
+    }
 }
 
+/// Sends a sample and, when it went out, tells the health check and systemd
+fn send(info: &mut SysInfo, sink: &mut NetworkSink, health: &Health, ready: &mut bool) {
+    if sink.emit(&info.sample()).is_ok() {
+        health.sent();
+        info.sent();
+        notify_sent(ready);
+    }
+}
+
+/// Called after each successful send: READY=1 the first time, and WATCHDOG=1
+/// every time when systemd's watchdog is on, so a hung collector gets
+/// restarted
+fn notify_sent(ready: &mut bool) {
+    let mut states = Vec::new();
+    if !*ready {
+        states.push("READY=1");
+        *ready = true;
+    }
+    if std::env::var_os("WATCHDOG_USEC").is_some() {
+        states.push("WATCHDOG=1");
+    }
+    if !states.is_empty() {
+        sd_notify(&states.join("\n"));
+    }
+}
+
+/// systemd tracks the process it started, so under `Type=notify` we stay in
+/// the foreground
 fn daemonize() {
+    if std::env::var_os("NOTIFY_SOCKET").is_some() {
+        return;
+    }
     unsafe {
         let ret = libc::fork();
         if ret == -1 {
@@ -208,22 +356,213 @@ fn daemonize() {
     }
 }
 
+/// The options read once at startup, which a reload leaves as they were
+fn warn_restart_needed(running: &Config, reloaded: &Config) {
+    let changed = [
+        ("--health-addr", running.health_addr != reloaded.health_addr),
+        ("--prometheus", running.prometheus != reloaded.prometheus),
+        ("--count", running.count != reloaded.count),
+        ("--jitter", running.jitter != reloaded.jitter),
+    ];
+    for (option, _) in changed.iter().filter(|(_, changed)| *changed) {
+        warn!("{option} changed, restart uptimed to apply it");
+    }
+}
+
+/// For picking the interface argument
+fn list_interfaces() {
+    let interfaces = match SysInfo::interface_names() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            eprintln!("Unable to list network interfaces: {err}");
+            std::process::exit(1)
+        }
+    };
+    println!(
+        "{:<16} {:<8} {:>16} {:>16}",
+        "INTERFACE", "STATE", "RX BYTES", "TX BYTES"
+    );
+    for interface in interfaces {
+        let counter = |kind| match SysInfo::net_stats(&interface, kind) {
+            Ok(bytes) => bytes.to_string(),
+            Err(_) => "-".to_string(),
+        };
+        let state = SysInfo::operstate(&interface).unwrap_or_else(|_| "-".to_string());
+        println!(
+            "{interface:<16} {state:<8} {:>16} {:>16}",
+            counter("r"),
+            counter("t")
+        );
+    }
+}
+
+/// For picking the filesystem argument
+fn list_filesystems() {
+    let mounts = match Mount::read() {
+        Ok(mounts) => mounts,
+        Err(err) => {
+            eprintln!("Unable to list filesystems: {err}");
+            std::process::exit(1)
+        }
+    };
+    println!("{:<32} {:<12} DEVICE", "FILESYSTEM", "TYPE");
+    for mount in mounts {
+        println!("{:<32} {:<12} {}", mount.path, mount.fs_type, mount.device);
+    }
+}
+
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 5 {
-        usage();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if Config::has_flag(&args, &["--help", "-h"]) {
+        println!("{USAGE}");
+        return;
+    }
+    if Config::has_flag(&args, &["--version"]) {
+        println!("uptimed {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}\n");
+            eprintln!("{USAGE}");
+            std::process::exit(1)
+        }
+    };
+    set_max_level(Level::from_verbosity(config.verbosity));
+    set_roots(&config);
+    match config.list {
+        Some(List::Interfaces) => return list_interfaces(),
+        Some(List::Filesystems) => return list_filesystems(),
+        None => {}
+    }
+    if let Err(problems) = config.validate() {
+        eprintln!("{problems}");
         std::process::exit(1)
     }
-    let destination = args[1].clone();
-    let namespace = args[2].clone();
-    let filesystem = args[3].clone();
-    let interface = args[4].clone();
 
-    let mut info = SysInfo::new(destination, namespace, filesystem, interface);
-    info.send();
-    daemonize();
+    // Network and cpu only cover the moment between `new` and `refresh`
+    // here, so they're close to 0.
+    if config.once {
+        let mut info = SysInfo::new(&config);
+        info.refresh();
+        let mut sink = Stdout::new(Encoder::new(&config, info.hostname()));
+        if let Err(err) = sink.emit(&info.sample()) {
+            error!("Unable to write the sample: {err}");
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    if let Some(address) = &config.prometheus {
+        // Bind before daemonizing so a bad address is reported on the terminal
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Unable to listen on {address}: {err}");
+                std::process::exit(1)
+            }
+        };
+        let mut info = SysInfo::new(&config);
+        daemonize();
+        sd_notify("READY=1");
+        info!("Serving Prometheus metrics on {address}");
+        serve_prometheus(&mut info, listener);
+        return;
+    }
+
+    // The first sample goes out straight away with the values collected by
+    // `new`, so net-rx/net-tx are 0 in it. Every later sample covers the
+    // interval since the one before.
+    // Stopping mid-sleep skips the partial interval rather than sending it.
+    // A reload takes effect when the current sleep ends.
+    let health_listener =
+        config
+            .health_addr
+            .as_ref()
+            .map(|address| match TcpListener::bind(address) {
+                Ok(listener) => (address, listener),
+                Err(err) => {
+                    error!("Unable to listen on {address}: {err}");
+                    std::process::exit(1)
+                }
+            });
+    let health = Health::new(config.interval);
+    let mut info = SysInfo::new(&config);
+    let mut sink = NetworkSink::new(&config, Encoder::new(&config, info.hostname()));
+    let mut interval = config.interval;
+    install_signal_handlers();
+    let addresses = sink.addresses();
+    info!(
+        "Sending to {} every {}s",
+        addresses.join(", "),
+        interval.as_secs()
+    );
+    let mut due = Instant::now();
+    let mut ready = false;
+    // Without jitter the first sample goes out before the fork, so a
+    // destination that can't be reached shows on the terminal. With it the
+    // wait happens in the background rather than holding up whatever
+    // started us.
+    if config.jitter.is_zero() {
+        send(&mut info, &mut sink, &health, &mut ready);
+    }
+    // A fixed number of samples is for scripts waiting on us to exit. The
+    // count is of samples taken, sent or not, and a reload doesn't change it.
+    let count = config.count;
+    let mut taken = 1;
+    if count == 0 {
+        daemonize();
+    }
+    // Threads don't survive the fork, so this one starts after it
+    if let Some((address, listener)) = health_listener {
+        info!("Answering health checks on {address}");
+        let health = health.clone();
+        thread::spawn(move || health.serve(listener));
+    }
+    if !config.jitter.is_zero() {
+        let seed = Jitter::seed(info.hostname(), std::process::id());
+        let delay = Jitter::new(config.jitter, seed).next_delay();
+        info!(
+            "Waiting {:.1}s before the first sample",
+            delay.as_secs_f64()
+        );
+        due += delay;
+        if !sleep_until(due) {
+            sd_notify("STOPPING=1");
+            return;
+        }
+        info.refresh();
+        send(&mut info, &mut sink, &health, &mut ready);
+    }
     loop {
-        info.send();
-        thread::sleep(Duration::from_secs(60));
+        if count != 0 && taken >= count {
+            info!("Took {taken} samples, exiting");
+            break;
+        }
+        due = next_deadline(due, interval, Instant::now());
+        if !sleep_until(due) {
+            break;
+        }
+        if RELOAD.swap(false, Ordering::Relaxed) {
+            match Config::from_args(&args).and_then(|config| config.validate().map(|()| config)) {
+                Ok(reloaded) => {
+                    warn_restart_needed(&config, &reloaded);
+                    set_max_level(Level::from_verbosity(reloaded.verbosity));
+                    set_roots(&reloaded);
+                    interval = reloaded.interval;
+                    health.set_interval(interval);
+                    info.reload(&reloaded);
+                    sink.reload(&reloaded, Encoder::new(&reloaded, info.hostname()));
+                    info!("Reloaded configuration");
+                }
+                Err(err) => error!("Keeping the old configuration, reload failed: {err}"),
+            }
+        }
+        info.refresh();
+        send(&mut info, &mut sink, &health, &mut ready);
+        taken += 1;
     }
+    sink.flush();
+    sd_notify("STOPPING=1");
 }
@@ -0,0 +1,620 @@
+use crate::{
+    Alert, SysInfo, TcpStats,
+    collect::{MEM_BREAKDOWN, NET_ERRORS},
+};
+use std::{fmt, time::Instant};
+
+/// Every metric the push formats send, by the name `--metrics` knows it as
+pub(crate) const METRICS: &[&str] = &[
+    "up",
+    "net-rx",
+    "net-tx",
+    "net-rx-err",
+    "net-tx-err",
+    "net-rx-drop",
+    "net-tx-drop",
+    "net-up",
+    "net-speed",
+    "tcp-retrans",
+    "conntrack",
+    "conntrack-pct",
+    "uptime",
+    "boottime",
+    "availmem",
+    "memused",
+    "membuffers",
+    "memcached",
+    "memdirty",
+    "memreclaimable",
+    "swapused",
+    "diskfree",
+    "diskused",
+    "inodefree",
+    "fs-readonly",
+    "disk-read",
+    "disk-write",
+    "load",
+    "load5",
+    "load15",
+    "procs",
+    "procs-running",
+    "procs-total",
+    "zombies",
+    "entropy",
+    "kernel",
+    "fd-used",
+    "fd-used-pct",
+    "cpu",
+    "cpusteal",
+    "iowait",
+    "ctxt",
+    "intr",
+    "cputemp",
+    "disktemp",
+    "psi-cpu",
+    "psi-mem",
+    "psi-io",
+    "collect-ms",
+    "send-lag",
+    "sends",
+];
+
+/// A metric value, kept typed so formats that care (Influx) can tell
+/// integers from floats
+#[derive(Clone, Copy)]
+pub enum Value {
+    Int(u64),
+    Float(f64),
+    /// A running total since startup, which StatsD wants as increments
+    Count(u64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value}"),
+            Value::Count(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/// One sample's values, for callers that want the numbers rather than a
+/// payload. Percentages are 0 to 100, unrounded. Metrics whose collector
+/// is disabled are `None`, or empty for the per device ones.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub hostname: String,
+    pub interfaces: Vec<InterfaceMetrics>,
+    pub interface_errors: Vec<InterfaceErrorMetrics>,
+    pub links: Vec<LinkMetrics>,
+    pub filesystems: Vec<FilesystemMetrics>,
+    pub disks: Vec<DiskMetrics>,
+    /// TCP segments retransmitted since the previous sample
+    pub tcp_retrans: Option<u64>,
+    /// The TCP counters since boot
+    pub tcp: Option<TcpStats>,
+    /// Connections in the netfilter tracking table
+    pub conntrack: Option<u64>,
+    /// Percent of nf_conntrack_max in use
+    pub conntrack_pct: Option<f64>,
+    /// Seconds since boot
+    pub uptime: Option<f32>,
+    /// Unix time of boot
+    pub boot_time: Option<u64>,
+    /// Percent of memory available
+    pub avail_mem: Option<f64>,
+    /// Bytes of memory available
+    pub avail_mem_bytes: Option<u64>,
+    /// Buffers, Cached, Dirty and SReclaimable as percent of memory
+    pub mem_breakdown: Option<[f64; 4]>,
+    /// The same in bytes
+    pub mem_breakdown_bytes: Option<[u64; 4]>,
+    /// Percent of swap in use, 0 without swap
+    pub swap_used: Option<f64>,
+    /// 1, 5 and 15 minute load averages as the kernel reports them
+    pub load_avg: Option<[f32; 3]>,
+    /// The load averages scaled to percent of the cores
+    pub load: Option<[f32; 3]>,
+    /// Processes and threads
+    pub procs: Option<u64>,
+    /// The runnable ones and all of them, 0 if /proc/loadavg didn't say
+    pub procs_running: Option<u64>,
+    pub procs_total: Option<u64>,
+    /// Processes that exited and haven't been reaped, `None` unless
+    /// `--enable zombies`
+    pub zombies: Option<u64>,
+    /// Bits in the kernel's entropy pool
+    pub entropy: Option<u64>,
+    /// The running kernel's release, e.g. `6.1.0-18-amd64`
+    pub kernel: Option<String>,
+    /// File handles in use across the system
+    pub fd_used: Option<u64>,
+    /// Percent of fs.file-max in use
+    pub fd_used_pct: Option<f64>,
+    /// Percent of CPU time spent busy since the previous sample
+    pub cpu: Option<f64>,
+    /// The same for each core, by core number
+    pub cpu_cores: Vec<(u32, f64)>,
+    /// Percent of CPU time stolen by the hypervisor since the previous sample
+    pub cpu_steal: Option<f64>,
+    /// Percent of CPU time idle waiting on I/O in the last interval
+    pub cpu_iowait: Option<f64>,
+    /// Context switches since the previous sample
+    pub ctxt: Option<u64>,
+    /// Interrupts since the previous sample
+    pub intr: Option<u64>,
+    /// Degrees C of the hottest thermal zone, `None` without sensors
+    pub cpu_temp: Option<f64>,
+    /// Degrees C of each drive with a sensor, by device
+    pub disk_temps: Vec<(String, f64)>,
+    /// `some avg10` pressure for cpu, memory and io, `None` without PSI
+    pub psi: Option<[f64; 3]>,
+    /// How long collecting this sample took
+    pub collect_ms: u64,
+    /// Seconds since a sample last went out, `None` until one has
+    pub send_lag: Option<f64>,
+    /// Samples sent, or attempted, since startup
+    pub sends: u64,
+    /// What `--metrics` and `--disable-metrics` left to send, `None` for
+    /// everything
+    pub(crate) enabled: Option<Vec<String>>,
+    /// `--absolute`, which sends availmem, the memory breakdown and diskfree
+    /// as bytes
+    pub(crate) absolute: bool,
+    /// `--precision`, the decimal places percentages and load are sent with
+    pub(crate) precision: u32,
+    /// `--alert` thresholds
+    pub(crate) alerts: Vec<Alert>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InterfaceMetrics {
+    pub name: String,
+    /// Bytes since the previous sample
+    pub rx: u64,
+    pub tx: u64,
+    /// The interface's byte counters
+    pub rx_total: u64,
+    pub tx_total: u64,
+    pub(crate) suffix: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct InterfaceErrorMetrics {
+    pub name: String,
+    /// rx_errors, tx_errors, rx_dropped and tx_dropped since the previous
+    /// sample
+    pub errors: [u64; 4],
+    /// The interface's counters, in the same order
+    pub totals: [u64; 4],
+    pub(crate) suffix: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkMetrics {
+    pub name: String,
+    pub up: bool,
+    /// Mbps, `None` for virtual interfaces
+    pub speed: Option<u64>,
+    pub(crate) suffix: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct FilesystemMetrics {
+    pub path: String,
+    /// Percent of the filesystem free
+    pub free: f64,
+    /// Bytes free to unprivileged users
+    pub free_bytes: u64,
+    /// Percent of its inodes free
+    pub inodes_free: f64,
+    /// Mounted read-only, often remounted so after a disk error
+    pub read_only: bool,
+    pub(crate) suffix: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskMetrics {
+    pub device: String,
+    /// Bytes since the previous sample
+    pub read: u64,
+    pub written: u64,
+    /// Bytes since boot
+    pub read_total: u64,
+    pub written_total: u64,
+}
+
+impl Metrics {
+    /// Every enabled metric under the name the push formats send it as, in
+    /// the order they're sent
+    pub fn named(&self) -> Vec<(String, Value)> {
+        let factor = 10f64.powi(self.precision as i32);
+        let round = |value: f64| Value::Float((value * factor).round() / factor);
+        // Sent whatever the collectors managed, so a silent host can be told
+        // apart from one whose /proc reads fail
+        let mut metrics = vec![("up".to_string(), Value::Int(1))];
+        for interface in &self.interfaces {
+            metrics.push((
+                format!("net-rx{}", interface.suffix),
+                Value::Int(interface.rx),
+            ));
+            metrics.push((
+                format!("net-tx{}", interface.suffix),
+                Value::Int(interface.tx),
+            ));
+        }
+        for interface in &self.interface_errors {
+            for ((_, name), errors) in NET_ERRORS.iter().zip(interface.errors) {
+                metrics.push((format!("{name}{}", interface.suffix), Value::Int(errors)));
+            }
+        }
+        for link in &self.links {
+            metrics.push((format!("net-up{}", link.suffix), Value::Int(link.up as u64)));
+            if let Some(speed) = link.speed {
+                metrics.push((format!("net-speed{}", link.suffix), Value::Int(speed)));
+            }
+        }
+        if let Some(tcp_retrans) = self.tcp_retrans {
+            metrics.push(("tcp-retrans".to_string(), Value::Int(tcp_retrans)));
+        }
+        if let Some(conntrack) = self.conntrack {
+            metrics.push(("conntrack".to_string(), Value::Int(conntrack)));
+        }
+        if let Some(conntrack_pct) = self.conntrack_pct {
+            metrics.push(("conntrack-pct".to_string(), round(conntrack_pct)));
+        }
+        if let Some(uptime) = self.uptime {
+            metrics.push(("uptime".to_string(), Value::Int(uptime as u64)));
+        }
+        if let Some(boot_time) = self.boot_time {
+            metrics.push(("boottime".to_string(), Value::Int(boot_time)));
+        }
+        match (self.absolute, self.avail_mem, self.avail_mem_bytes) {
+            (false, Some(avail_mem), _) => {
+                metrics.push(("availmem".to_string(), round(avail_mem)));
+            }
+            (true, _, Some(bytes)) => {
+                metrics.push(("availmem-bytes".to_string(), Value::Int(bytes)));
+            }
+            _ => {}
+        }
+        // The complements are always percent, alerts are written against
+        // them the way they are against load
+        if let Some(avail_mem) = self.avail_mem {
+            metrics.push(("memused".to_string(), round(100.0 - avail_mem)));
+        }
+        match (self.absolute, self.mem_breakdown, self.mem_breakdown_bytes) {
+            (false, Some(percent), _) => {
+                for (name, percent) in MEM_BREAKDOWN.iter().zip(percent) {
+                    metrics.push((name.to_string(), round(percent)));
+                }
+            }
+            (true, _, Some(bytes)) => {
+                for (name, bytes) in MEM_BREAKDOWN.iter().zip(bytes) {
+                    metrics.push((format!("{name}-bytes"), Value::Int(bytes)));
+                }
+            }
+            _ => {}
+        }
+        if let Some(swap_used) = self.swap_used {
+            metrics.push(("swapused".to_string(), round(swap_used)));
+        }
+        for filesystem in &self.filesystems {
+            metrics.push(match self.absolute {
+                false => (
+                    format!("diskfree{}", filesystem.suffix),
+                    round(filesystem.free),
+                ),
+                true => (
+                    format!("diskfree-bytes{}", filesystem.suffix),
+                    Value::Int(filesystem.free_bytes),
+                ),
+            });
+            metrics.push((
+                format!("diskused{}", filesystem.suffix),
+                round(100.0 - filesystem.free),
+            ));
+            metrics.push((
+                format!("inodefree{}", filesystem.suffix),
+                round(filesystem.inodes_free),
+            ));
+            metrics.push((
+                format!("fs-readonly{}", filesystem.suffix),
+                Value::Int(filesystem.read_only as u64),
+            ));
+        }
+        for disk in &self.disks {
+            metrics.push((format!("disk-read.{}", disk.device), Value::Int(disk.read)));
+            metrics.push((
+                format!("disk-write.{}", disk.device),
+                Value::Int(disk.written),
+            ));
+        }
+        if let Some(load) = self.load {
+            for (name, load) in ["load", "load5", "load15"].iter().zip(load) {
+                metrics.push((name.to_string(), round(load as f64)));
+            }
+        }
+        if let Some(procs) = self.procs {
+            metrics.push(("procs".to_string(), Value::Int(procs)));
+        }
+        if let Some(running) = self.procs_running {
+            metrics.push(("procs-running".to_string(), Value::Int(running)));
+        }
+        if let Some(total) = self.procs_total {
+            metrics.push(("procs-total".to_string(), Value::Int(total)));
+        }
+        if let Some(zombies) = self.zombies {
+            metrics.push(("zombies".to_string(), Value::Int(zombies)));
+        }
+        if let Some(entropy) = self.entropy {
+            metrics.push(("entropy".to_string(), Value::Int(entropy)));
+        }
+        if let Some(version) = self
+            .kernel
+            .as_deref()
+            .and_then(SysInfo::kernel_version_number)
+        {
+            metrics.push(("kernel".to_string(), Value::Int(version)));
+        }
+        if let Some(fd_used) = self.fd_used {
+            metrics.push(("fd-used".to_string(), Value::Int(fd_used)));
+        }
+        if let Some(fd_used_pct) = self.fd_used_pct {
+            metrics.push(("fd-used-pct".to_string(), round(fd_used_pct)));
+        }
+        if let Some(cpu) = self.cpu {
+            metrics.push(("cpu".to_string(), round(cpu)));
+        }
+        for (core, busy) in &self.cpu_cores {
+            metrics.push((format!("cpu.{core}"), round(*busy)));
+        }
+        if let Some(cpu_steal) = self.cpu_steal {
+            metrics.push(("cpusteal".to_string(), round(cpu_steal)));
+        }
+        if let Some(cpu_iowait) = self.cpu_iowait {
+            metrics.push(("iowait".to_string(), round(cpu_iowait)));
+        }
+        if let Some(ctxt) = self.ctxt {
+            metrics.push(("ctxt".to_string(), Value::Int(ctxt)));
+        }
+        if let Some(intr) = self.intr {
+            metrics.push(("intr".to_string(), Value::Int(intr)));
+        }
+        if let Some(cpu_temp) = self.cpu_temp {
+            metrics.push(("cputemp".to_string(), round(cpu_temp)));
+        }
+        for (device, temp) in &self.disk_temps {
+            metrics.push((format!("disktemp.{device}"), round(*temp)));
+        }
+        if let Some(psi) = self.psi {
+            for (name, value) in ["psi-cpu", "psi-mem", "psi-io"].iter().zip(psi) {
+                metrics.push((name.to_string(), round(value)));
+            }
+        }
+        metrics.push(("collect-ms".to_string(), Value::Int(self.collect_ms)));
+        if let Some(send_lag) = self.send_lag {
+            let send_lag = (send_lag * 1000.0).round() / 1000.0;
+            metrics.push(("send-lag".to_string(), Value::Float(send_lag)));
+        }
+        metrics.push(("sends".to_string(), Value::Count(self.sends)));
+        // A NaN or inf would be rejected by the server, or worse, stored
+        metrics.retain(|(_, value)| !matches!(value, Value::Float(value) if !value.is_finite()));
+        if let Some(enabled) = &self.enabled {
+            metrics.retain(|(name, _)| {
+                // The --absolute names are chosen by their percent name
+                let base = name.split('.').next().unwrap_or(name);
+                let base = base.strip_suffix("-bytes").unwrap_or(base);
+                enabled.iter().any(|metric| metric == base)
+            });
+        }
+        // Every sample says whether each alert is on, so one that stops
+        // firing goes back to 0. Each filesystem or interface has its own.
+        let mut alerts = Vec::new();
+        for alert in &self.alerts {
+            for (name, value) in &metrics {
+                let value = match value {
+                    Value::Int(value) => *value as f64,
+                    Value::Float(value) => *value,
+                    Value::Count(_) => continue,
+                };
+                if name.split('.').next() == Some(alert.metric.as_str()) {
+                    let firing = alert.firing(value);
+                    alerts.push((format!("alert.{name}"), Value::Int(firing as u64)));
+                }
+            }
+        }
+        metrics.extend(alerts);
+        metrics
+    }
+}
+
+impl SysInfo {
+    /// Refreshes and returns the new sample, for callers doing their own
+    /// sending
+    pub fn collect(&mut self) -> Metrics {
+        self.refresh();
+        self.metrics()
+    }
+
+    /// The current sample, counted as a send. Call once for each sample
+    /// handed to a sink.
+    pub fn sample(&mut self) -> Metrics {
+        self.sends += 1;
+        self.metrics()
+    }
+
+    /// Records that the last sample reached the sink, which the next
+    /// sample's send-lag is counted from. Without a call after a failed
+    /// send the lag keeps growing, by an interval for each one missed.
+    pub fn sent(&mut self) {
+        self.last_sent = Some(Instant::now());
+    }
+
+    /// The current sample
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = Metrics {
+            hostname: self.hostname.clone(),
+            collect_ms: self.collect_ms,
+            send_lag: self.last_sent.map(|sent| sent.elapsed().as_secs_f64()),
+            sends: self.sends,
+            enabled: self.enabled.clone(),
+            absolute: self.absolute,
+            precision: self.precision,
+            alerts: self.alerts.clone(),
+            ..Metrics::default()
+        };
+        for collector in &self.collectors {
+            collector.report(&mut metrics);
+        }
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Encoder,
+        testing::{self, TempDir},
+    };
+    use std::{thread, time::Duration};
+
+    /// The value `named()` gives `name`, as it's written on the wire
+    fn value(metrics: &Metrics, name: &str) -> Option<String> {
+        metrics
+            .named()
+            .into_iter()
+            .find(|(metric, _)| metric == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn only_chosen_metrics() {
+        let config = testing::config(&[
+            "--metrics",
+            "net-rx,net-tx,uptime",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let metrics = SysInfo::new(&config).metrics();
+        let names: Vec<String> = metrics.named().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["net-rx", "net-tx", "uptime"]);
+    }
+
+    #[test]
+    fn precision() {
+        // Percentages are whole numbers unless --precision asks for more
+        let config = testing::config(&["localhost", "myapp", "/", "lo"]);
+        let mut metrics = SysInfo::new(&config).metrics();
+        metrics.avail_mem = Some(79.4321);
+        assert_eq!(value(&metrics, "availmem").as_deref(), Some("79"));
+        let config = testing::config(&["--precision", "2", "localhost", "myapp", "/", "lo"]);
+        let mut metrics = SysInfo::new(&config).metrics();
+        metrics.avail_mem = Some(79.4321);
+        metrics.cpu = Some(f64::NAN);
+        assert_eq!(value(&metrics, "availmem").as_deref(), Some("79.43"));
+        assert_eq!(value(&metrics, "cpu"), None);
+        metrics.psi = Some([12.345, 0.0, 1.5]);
+        assert_eq!(value(&metrics, "psi-cpu").as_deref(), Some("12.35"));
+    }
+
+    #[test]
+    fn alerts() {
+        // --alert adds alert.<metric>, 1 while the threshold is crossed
+        let config = testing::config(&["--alert", "availmem<20", "localhost", "myapp", "/", "lo"]);
+        let mut metrics = SysInfo::new(&config).metrics();
+        metrics.avail_mem = Some(15.0);
+        assert_eq!(value(&metrics, "alert.availmem").as_deref(), Some("1"));
+        metrics.avail_mem = Some(35.0);
+        assert_eq!(value(&metrics, "alert.availmem").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn used_complements_free() {
+        // diskused and memused are what diskfree and availmem leave, each
+        // rounded on its own
+        let config = testing::config(&["localhost", "myapp", "/", "lo"]);
+        let metrics = SysInfo::new(&config).metrics();
+        let value = |name: &str| value(&metrics, name).unwrap().parse::<f64>().unwrap();
+        assert!((value("diskused") + value("diskfree") - 100.0).abs() <= 1.0);
+        assert!((value("memused") + value("availmem") - 100.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn up_leads() {
+        // up leads every sample, even one where nothing could be read
+        let named = Metrics::default().named();
+        let names: Vec<&str> = named.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["up", "collect-ms", "sends"]);
+        assert_eq!(named[0].1.to_string(), "1");
+
+        // A host where every collector fails still sends it first
+        let empty = TempDir::new("up-leads");
+        let root = empty.path().to_str().unwrap();
+        let config = testing::config(&[
+            "--proc-root",
+            root,
+            "--sys-root",
+            root,
+            "localhost",
+            "myapp",
+            "/no/such/dir",
+            "lo",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        info.refresh();
+        let statsd = Encoder::new(&config, "web-01").serialize(&info.sample());
+        assert_eq!(
+            statsd.lines().next(),
+            Some("myapp.web-01.up:1|g"),
+            "{statsd}"
+        );
+    }
+
+    #[test]
+    fn self_monitoring() {
+        let config = testing::config(&[
+            "--metrics",
+            "collect-ms,sends",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let mut info = SysInfo::new(&config);
+        info.refresh();
+        for sends in 1..=3 {
+            let named = info.sample().named();
+            let value = |metric: &str| {
+                named
+                    .iter()
+                    .find(|(name, _)| name == metric)
+                    .map(|(_, value)| *value)
+            };
+            assert!(matches!(value("collect-ms"), Some(Value::Int(ms)) if ms < 10_000));
+            assert!(matches!(value("sends"), Some(Value::Count(n)) if n == sends));
+        }
+    }
+
+    #[test]
+    fn send_lag() {
+        let config = testing::config(&["--metrics", "uptime", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        assert_eq!(info.sample().send_lag, None);
+        info.sent();
+        thread::sleep(Duration::from_millis(50));
+        let delivered = info.sample().send_lag.unwrap();
+        assert!(delivered >= 0.05);
+        // That send failed, so the next lag covers both intervals
+        thread::sleep(Duration::from_millis(50));
+        let missed = info.sample().send_lag.unwrap();
+        assert!(missed >= 0.1 && missed > delivered);
+        info.sent();
+        assert!(info.sample().send_lag.unwrap() < missed);
+    }
+}
@@ -0,0 +1,542 @@
+use crate::{
+    Metrics, SysInfo,
+    metrics::{DiskMetrics, FilesystemMetrics},
+    send::TCP_TIMEOUT,
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+};
+
+impl Metrics {
+    /// Format metrics in the Prometheus text exposition format
+    /// <https://prometheus.io/docs/instrumenting/exposition_formats/>
+    /// Network metrics are the interface's byte counters rather than per
+    /// interval deltas, Prometheus works out rates itself.
+    pub fn serialize_prometheus(&self) -> String {
+        let sample = self;
+        let escape = |s: &str| {
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        };
+        let host = format!("host=\"{}\"", escape(&sample.hostname));
+        let device = |name: &str| format!("{host},device=\"{}\"", escape(name));
+        let disk = |disk: &DiskMetrics| format!("{host},device=\"{}\"", escape(&disk.device));
+        let mountpoint = |filesystem: &FilesystemMetrics| {
+            format!("{host},mountpoint=\"{}\"", escape(&filesystem.path))
+        };
+        let metrics = [
+            (
+                "node_uptime_seconds",
+                "gauge",
+                "Seconds since boot",
+                sample
+                    .uptime
+                    .map(|uptime| (host.clone(), uptime.to_string()))
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                "node_nf_conntrack_entries",
+                "gauge",
+                "Connections in the netfilter tracking table",
+                sample
+                    .conntrack
+                    .map(|conntrack| (host.clone(), conntrack.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_boot_time_seconds",
+                "gauge",
+                "Unix time of boot",
+                sample
+                    .boot_time
+                    .map(|boot_time| (host.clone(), boot_time.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_memory_available_ratio",
+                "gauge",
+                "Fraction of memory available",
+                sample
+                    .avail_mem
+                    .map(|avail_mem| (host.clone(), (avail_mem / 100.0).to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_memory_available_bytes",
+                "gauge",
+                "Bytes of memory available",
+                sample
+                    .avail_mem_bytes
+                    .map(|bytes| (host.clone(), bytes.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_load1",
+                "gauge",
+                "One minute load average",
+                sample
+                    .load_avg
+                    .map(|load| (host.clone(), load[0].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_load5",
+                "gauge",
+                "Five minute load average",
+                sample
+                    .load_avg
+                    .map(|load| (host.clone(), load[1].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_load15",
+                "gauge",
+                "Fifteen minute load average",
+                sample
+                    .load_avg
+                    .map(|load| (host.clone(), load[2].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_memory_Buffers_bytes",
+                "gauge",
+                "Bytes of memory used for block device buffers",
+                sample
+                    .mem_breakdown_bytes
+                    .map(|bytes| (host.clone(), bytes[0].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_memory_Cached_bytes",
+                "gauge",
+                "Bytes of memory used for the page cache",
+                sample
+                    .mem_breakdown_bytes
+                    .map(|bytes| (host.clone(), bytes[1].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_memory_Dirty_bytes",
+                "gauge",
+                "Bytes of memory waiting to be written back to disk",
+                sample
+                    .mem_breakdown_bytes
+                    .map(|bytes| (host.clone(), bytes[2].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_memory_SReclaimable_bytes",
+                "gauge",
+                "Bytes of reclaimable slab memory",
+                sample
+                    .mem_breakdown_bytes
+                    .map(|bytes| (host.clone(), bytes[3].to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_processes_zombies",
+                "gauge",
+                "Processes that exited and haven't been reaped",
+                sample
+                    .zombies
+                    .map(|zombies| (host.clone(), zombies.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_entropy_available_bits",
+                "gauge",
+                "Bits in the kernel's entropy pool",
+                sample
+                    .entropy
+                    .map(|entropy| (host.clone(), entropy.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_uname_info",
+                "gauge",
+                "The running kernel's release, always 1",
+                sample
+                    .kernel
+                    .as_ref()
+                    .map(|release| {
+                        (
+                            format!("{host},release=\"{}\"", escape(release)),
+                            "1".to_string(),
+                        )
+                    })
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_filefd_allocated",
+                "gauge",
+                "File handles open across the system",
+                sample
+                    .fd_used
+                    .map(|fd_used| (host.clone(), fd_used.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_cpu_temperature_celsius",
+                "gauge",
+                "Temperature of the hottest thermal zone",
+                sample
+                    .cpu_temp
+                    .map(|temp| (host.clone(), temp.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_disk_temperature_celsius",
+                "gauge",
+                "Degrees C of the drive's hottest sensor",
+                sample
+                    .disk_temps
+                    .iter()
+                    .map(|(name, temp)| (device(name), temp.to_string()))
+                    .collect(),
+            ),
+            (
+                "node_filesystem_avail_ratio",
+                "gauge",
+                "Fraction of the filesystem available",
+                sample
+                    .filesystems
+                    .iter()
+                    .map(|f| (mountpoint(f), (f.free / 100.0).to_string()))
+                    .collect(),
+            ),
+            (
+                "node_filesystem_avail_bytes",
+                "gauge",
+                "Bytes of the filesystem available to unprivileged users",
+                sample
+                    .filesystems
+                    .iter()
+                    .map(|f| (mountpoint(f), f.free_bytes.to_string()))
+                    .collect(),
+            ),
+            (
+                "node_filesystem_files_avail_ratio",
+                "gauge",
+                "Fraction of the filesystem's inodes available",
+                sample
+                    .filesystems
+                    .iter()
+                    .map(|f| (mountpoint(f), (f.inodes_free / 100.0).to_string()))
+                    .collect(),
+            ),
+            (
+                "node_filesystem_readonly",
+                "gauge",
+                "Whether the filesystem is mounted read-only",
+                sample
+                    .filesystems
+                    .iter()
+                    .map(|f| (mountpoint(f), (f.read_only as u8).to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_receive_bytes",
+                "counter",
+                "Bytes received by the interface",
+                sample
+                    .interfaces
+                    .iter()
+                    .map(|i| (device(&i.name), i.rx_total.to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_transmit_bytes",
+                "counter",
+                "Bytes transmitted by the interface",
+                sample
+                    .interfaces
+                    .iter()
+                    .map(|i| (device(&i.name), i.tx_total.to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_receive_errs",
+                "counter",
+                "Receive errors on the interface",
+                sample
+                    .interface_errors
+                    .iter()
+                    .map(|i| (device(&i.name), i.totals[0].to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_transmit_errs",
+                "counter",
+                "Transmit errors on the interface",
+                sample
+                    .interface_errors
+                    .iter()
+                    .map(|i| (device(&i.name), i.totals[1].to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_receive_drop",
+                "counter",
+                "Received packets the interface dropped",
+                sample
+                    .interface_errors
+                    .iter()
+                    .map(|i| (device(&i.name), i.totals[2].to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_transmit_drop",
+                "counter",
+                "Outgoing packets the interface dropped",
+                sample
+                    .interface_errors
+                    .iter()
+                    .map(|i| (device(&i.name), i.totals[3].to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_up",
+                "gauge",
+                "Whether the interface's link is up",
+                sample
+                    .links
+                    .iter()
+                    .map(|l| (device(&l.name), (l.up as u8).to_string()))
+                    .collect(),
+            ),
+            (
+                "node_network_speed_bytes",
+                "gauge",
+                "Link speed in bytes per second",
+                sample
+                    .links
+                    .iter()
+                    .filter_map(|l| Some((device(&l.name), (l.speed? * 125_000).to_string())))
+                    .collect(),
+            ),
+            (
+                "node_netstat_Tcp_ActiveOpens",
+                "counter",
+                "TCP connections opened",
+                sample
+                    .tcp
+                    .map(|tcp| (host.clone(), tcp.active_opens.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_netstat_Tcp_InSegs",
+                "counter",
+                "TCP segments received",
+                sample
+                    .tcp
+                    .map(|tcp| (host.clone(), tcp.in_segs.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_netstat_Tcp_OutSegs",
+                "counter",
+                "TCP segments sent",
+                sample
+                    .tcp
+                    .map(|tcp| (host.clone(), tcp.out_segs.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_netstat_Tcp_RetransSegs",
+                "counter",
+                "TCP segments retransmitted",
+                sample
+                    .tcp
+                    .map(|tcp| (host.clone(), tcp.retrans_segs.to_string()))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "node_disk_read_bytes",
+                "counter",
+                "Bytes read from the block device",
+                sample
+                    .disks
+                    .iter()
+                    .map(|d| (disk(d), d.read_total.to_string()))
+                    .collect(),
+            ),
+            (
+                "node_disk_written_bytes",
+                "counter",
+                "Bytes written to the block device",
+                sample
+                    .disks
+                    .iter()
+                    .map(|d| (disk(d), d.written_total.to_string()))
+                    .collect(),
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, samples) in metrics {
+            // Disabled collectors and empty device lists leave nothing to
+            // describe
+            if samples.is_empty() {
+                continue;
+            }
+            out += &format!("# HELP {name} {help}\n# TYPE {name} {kind}\n");
+            for (labels, value) in samples {
+                out += &format!("{name}{{{labels}}} {value}\n");
+            }
+        }
+        out
+    }
+}
+
+/// A deliberately minimal HTTP/1.1 responder: one request per connection,
+/// collecting fresh metrics for each scrape of /metrics
+pub fn serve_prometheus(info: &mut SysInfo, listener: TcpListener) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Unable to accept Prometheus connection: {err}");
+                continue;
+            }
+        };
+        let _ = stream.set_read_timeout(Some(TCP_TIMEOUT));
+        let _ = stream.set_write_timeout(Some(TCP_TIMEOUT));
+        let mut request_line = String::new();
+        let mut reader = BufReader::new(&stream);
+        if reader.read_line(&mut request_line).is_err() {
+            continue;
+        }
+        // Drain the headers, we don't need any of them
+        let mut header = String::new();
+        while reader
+            .read_line(&mut header)
+            .is_ok_and(|n| n > 0 && header.trim() != "")
+        {
+            header.clear();
+        }
+        let mut parts = request_line.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => {
+                info.refresh();
+                let body = info.metrics().serialize_prometheus();
+                debug!("Answering scrape with {} bytes", body.len());
+                format!(
+                    "HTTP/1.1 200 OK\r\n\
+                     Content-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+            _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        };
+        if let Err(err) = stream.write_all(response.as_bytes()) {
+            warn!("Unable to answer Prometheus scrape: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics::InterfaceMetrics, testing};
+    use std::{io::Read, net::TcpStream, thread};
+
+    #[test]
+    fn serialize() {
+        let metrics = Metrics {
+            hostname: "web\"01".to_string(),
+            uptime: Some(3600.0),
+            avail_mem: Some(80.0),
+            load_avg: Some([0.5, 0.25, 0.125]),
+            interfaces: vec![InterfaceMetrics {
+                name: "eth0".to_string(),
+                rx: 10,
+                rx_total: 1000,
+                tx_total: 2000,
+                ..InterfaceMetrics::default()
+            }],
+            ..Metrics::default()
+        };
+        let text = metrics.serialize_prometheus();
+        for expected in [
+            "# HELP node_uptime_seconds Seconds since boot",
+            "# TYPE node_uptime_seconds gauge",
+            "node_uptime_seconds{host=\"web\\\"01\"} 3600",
+            "node_memory_available_ratio{host=\"web\\\"01\"} 0.8",
+            "node_load1{host=\"web\\\"01\"} 0.5",
+            "node_load15{host=\"web\\\"01\"} 0.125",
+            "# TYPE node_network_receive_bytes counter",
+            // The counter itself, not the delta since the last sample
+            "node_network_receive_bytes{host=\"web\\\"01\",device=\"eth0\"} 1000",
+            "node_network_transmit_bytes{host=\"web\\\"01\",device=\"eth0\"} 2000",
+        ] {
+            assert!(
+                text.lines().any(|line| line == expected),
+                "{expected}\n{text}"
+            );
+        }
+        // Nothing was collected for them
+        assert!(!text.contains("node_boot_time_seconds"));
+        assert!(!text.contains("node_filesystem_avail_ratio"));
+    }
+
+    /// The response to `request`, whole
+    fn get(address: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        // Never returns, the thread ends with the test binary
+        thread::spawn(move || {
+            let mut info = SysInfo::new(&testing::config(&["localhost", "myapp", "/", "lo"]));
+            serve_prometheus(&mut info, listener)
+        });
+
+        let response = get(&address, "GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains("\nnode_uptime_seconds{host=\""), "{body}");
+        assert!(body.contains("node_network_receive_bytes{host=\""));
+
+        for request in ["GET / HTTP/1.1\r\n\r\n", "POST /metrics HTTP/1.1\r\n\r\n"] {
+            let response = get(&address, request);
+            assert!(
+                response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{response}"
+            );
+        }
+    }
+}
@@ -0,0 +1,1071 @@
+use crate::{
+    Config, Encoder, Endpoint, Format, MetricSink, Metrics, Protocol, Syslog, sysinfo::unix_time,
+};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    mem,
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    os::unix::{
+        fs::MetadataExt,
+        net::{UnixDatagram, UnixStream},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a TCP connect or write, or a Unix datagram write to a full
+/// socket, may block before we give up until the next interval
+pub(crate) const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `host:port`, with an IPv6 address in brackets so its colons aren't
+/// taken for the port's
+pub(crate) fn host_port(host: &str, port: u16) -> String {
+    match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    }
+}
+
+/// The wait before the first `--send-retries` retry, doubling for each
+/// one after it
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(4);
+
+/// Somewhere samples are sent
+struct Destination {
+    /// A host name or address, or a socket path for `Protocol::Unix`
+    host: String,
+    port: u16,
+    protocol: Protocol,
+    /// The request path for `Protocol::Http`, empty otherwise
+    path: String,
+    /// The last resolved address and when it was looked up
+    resolved: Option<(SocketAddr, Instant)>,
+    /// Kept open across sends and reconnected whenever a write fails
+    tcp: Option<TcpStream>,
+    /// The same for Collectd's unixsock plugin, read for its replies
+    collectd: Option<BufReader<UnixStream>>,
+    /// Samples that couldn't be sent yet, oldest first
+    backlog: VecDeque<String>,
+}
+
+impl Destination {
+    /// A host starting with `/` is a Unix socket path whatever `protocol`
+    /// says
+    fn new(host: &str, port: u16, protocol: Protocol) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            protocol: match host.starts_with('/') {
+                true => Protocol::Unix,
+                false => protocol,
+            },
+            path: String::new(),
+            resolved: None,
+            tcp: None,
+            collectd: None,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    fn endpoint(endpoint: &Endpoint) -> Self {
+        Self {
+            path: endpoint.path.clone(),
+            ..Self::new(&endpoint.host, endpoint.port, Protocol::Http)
+        }
+    }
+
+    /// Where samples go, for sending and for messages
+    fn address(&self) -> String {
+        match self.protocol {
+            Protocol::Unix => self.host.clone(),
+            Protocol::Http => format!("http://{}{}", host_port(&self.host, self.port), self.path),
+            _ => host_port(&self.host, self.port),
+        }
+    }
+
+    /// The cached address while it's younger than the TTL, otherwise a fresh
+    /// lookup. If that lookup fails the stale address is better than nothing.
+    fn resolve(&mut self, ttl: Duration) -> io::Result<SocketAddr> {
+        if let Some((addr, at)) = self.resolved
+            && at.elapsed() < ttl
+        {
+            return Ok(addr);
+        }
+        let address = self.address();
+        let lookup = host_port(&self.host, self.port)
+            .to_socket_addrs()
+            .and_then(|mut addrs| {
+                addrs.next().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{address} did not resolve"),
+                    )
+                })
+            });
+        match (lookup, self.resolved) {
+            (Ok(addr), _) => {
+                if self.resolved.is_some_and(|(old, _)| old != addr) {
+                    info!("{address} now resolves to {addr}");
+                }
+                self.resolved = Some((addr, Instant::now()));
+                Ok(addr)
+            }
+            (Err(err), Some((addr, _))) => {
+                warn!("Unable to resolve {address}, still using {addr}: {err}");
+                Ok(addr)
+            }
+            (Err(err), None) => Err(err),
+        }
+    }
+
+    /// A write on an existing connection that fails (server restarted,
+    /// broken pipe) gets one immediate retry on a fresh connection. If that
+    /// fails too the connection is dropped and retried next interval.
+    fn send_tcp(&mut self, address: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        if let Some(stream) = self.tcp.as_mut() {
+            if stream.write_all(payload).is_ok() {
+                return Ok(());
+            }
+            self.tcp = None;
+        }
+        let mut stream = Self::connect_tcp(address)?;
+        stream.write_all(payload)?;
+        self.tcp = Some(stream);
+        Ok(())
+    }
+
+    /// Collectd's unixsock plugin takes the PUTVAL lines on a stream socket
+    /// and answers each with a status line, negative when it refused the
+    /// value. A refusal is logged and the rest of the sample still counts as
+    /// sent, it has been taken and sending it again would only duplicate it.
+    /// A connection that fails is dropped and the next send connects again.
+    fn send_collectd(&mut self, payload: &str) -> io::Result<()> {
+        let mut stream = match self.collectd.take() {
+            Some(stream) => stream,
+            None => {
+                let stream = UnixStream::connect(&self.host)?;
+                stream.set_read_timeout(Some(TCP_TIMEOUT))?;
+                stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                BufReader::new(stream)
+            }
+        };
+        stream.get_mut().write_all(payload.as_bytes())?;
+        for line in payload.lines() {
+            let mut reply = String::new();
+            if stream.read_line(&mut reply)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "collectd closed the connection",
+                ));
+            }
+            if reply.starts_with('-') {
+                warn!("collectd refused {line}: {}", reply.trim_end());
+            }
+        }
+        self.collectd = Some(stream);
+        Ok(())
+    }
+
+    /// One request per connection, an export every interval doesn't need
+    /// keep-alive. Anything but a 2xx status fails the send.
+    fn send_http(&self, address: SocketAddr, payload: &str) -> io::Result<()> {
+        let mut stream = Self::connect_tcp(address)?;
+        stream.set_read_timeout(Some(TCP_TIMEOUT))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{payload}",
+            self.path,
+            host_port(&self.host, self.port),
+            payload.len(),
+        );
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        let mut buf = [0; 512];
+        while !response.contains(&b'\n') {
+            match stream.read(&mut buf)? {
+                0 => break,
+                n => response.extend_from_slice(&buf[..n]),
+            }
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or("").trim();
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!("the server answered {status:?}"))),
+        }
+    }
+
+    fn connect_tcp(address: SocketAddr) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&address, TCP_TIMEOUT)?;
+        stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+        Ok(stream)
+    }
+}
+
+/// `--output-file`, kept open between samples
+struct OutputFile {
+    path: String,
+    /// The open file and its inode
+    file: Option<(File, u64)>,
+}
+
+impl OutputFile {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            file: None,
+        }
+    }
+
+    /// Appends the sample under a `# <unix time>` line. When logrotate has
+    /// moved the file away, or deleted it, a new one is started at the path.
+    /// A failed write (the disk is full) leaves whatever part of the sample
+    /// got out, and the next sample is tried as usual.
+    fn write(&mut self, payload: &str) -> io::Result<()> {
+        let inode = fs::metadata(&self.path).map(|metadata| metadata.ino()).ok();
+        let (mut file, open_inode) = match self.file.take() {
+            Some((file, open_inode)) if Some(open_inode) == inode => (file, open_inode),
+            previous => {
+                if previous.is_some() {
+                    info!("{} was moved, reopening it", self.path);
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                let inode = file.metadata()?.ino();
+                (file, inode)
+            }
+        };
+        let sample = format!("# {}\n{payload}", unix_time().as_secs());
+        let written = file
+            .write_all(sample.as_bytes())
+            .and_then(|()| file.flush());
+        self.file = Some((file, open_inode));
+        written
+    }
+}
+
+/// Sends samples to every configured StatsD, InfluxDB or Graphite server
+/// over UDP, TCP or a Unix datagram socket, or to Collectd's unixsock
+/// plugin
+///
+/// With `--send-retries` a failed send is tried again straight away, backing
+/// off between tries, before it's left to the next interval.
+///
+/// With `--output-file` samples are appended to a file as well, which is
+/// started again when it's rotated away.
+pub struct NetworkSink {
+    encoder: Encoder,
+    destinations: Vec<Destination>,
+    resolve_ttl: Duration,
+    buffer_size: usize,
+    /// Extra tries a failed send gets before waiting for the next interval
+    send_retries: u32,
+    /// The largest datagram sent over UDP or a Unix socket
+    max_packet_size: usize,
+    /// `--format collectd`, whose socket paths are streams
+    collectd: bool,
+    /// `--bind`, the UDP sockets' source address
+    bind: Option<IpAddr>,
+    /// Shared by every IPv4 UDP destination. Bound once and reused, rebound
+    /// on the next send if a send fails
+    udp: Option<UdpSocket>,
+    /// The same for IPv6 destinations, bound on the first send to one. An
+    /// IPv4 socket can't send to an IPv6 address or the other way round, so
+    /// each family gets its own.
+    udp6: Option<UdpSocket>,
+    /// Unbound and shared, each payload is addressed to a destination path
+    unix: Option<UnixDatagram>,
+    syslog: Option<Syslog>,
+    output_file: Option<OutputFile>,
+}
+
+impl NetworkSink {
+    pub fn new(config: &Config, encoder: Encoder) -> Self {
+        Self {
+            encoder,
+            destinations: Self::destinations(config, Vec::new()),
+            resolve_ttl: config.resolve_ttl,
+            buffer_size: config.buffer_size,
+            send_retries: config.send_retries,
+            max_packet_size: config.max_packet_size,
+            collectd: config.format == Format::Collectd,
+            bind: config.bind,
+            udp: match config.protocol {
+                Protocol::Udp => Self::bind_udp(config.bind, false).ok(),
+                _ => None,
+            },
+            udp6: None,
+            unix: None,
+            syslog: config.syslog.clone(),
+            output_file: config.output_file.as_deref().map(OutputFile::new),
+        }
+    }
+
+    /// Applies a reloaded configuration, see [`NetworkSink::destinations`]
+    /// for what's kept
+    pub fn reload(&mut self, config: &Config, encoder: Encoder) {
+        self.encoder = encoder;
+        self.destinations = Self::destinations(config, mem::take(&mut self.destinations));
+        self.resolve_ttl = config.resolve_ttl;
+        self.buffer_size = config.buffer_size;
+        self.send_retries = config.send_retries;
+        self.max_packet_size = config.max_packet_size;
+        self.collectd = config.format == Format::Collectd;
+        if self.bind != config.bind {
+            // Bound again from the new address on the next send
+            self.bind = config.bind;
+            self.udp = None;
+            self.udp6 = None;
+        }
+        self.syslog = config.syslog.clone();
+        if self.output_file.as_ref().map(|file| &file.path) != config.output_file.as_ref() {
+            self.output_file = config.output_file.as_deref().map(OutputFile::new);
+        }
+    }
+
+    /// Where samples go, for messages
+    pub fn addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<String> =
+            self.destinations.iter().map(Destination::address).collect();
+        if let Some(syslog) = &self.syslog {
+            addresses.push(format!("syslog at {}", syslog.socket));
+        }
+        if let Some(file) = &self.output_file {
+            addresses.push(file.path.clone());
+        }
+        addresses
+    }
+
+    /// Destinations that are still configured keep their cached address and
+    /// TCP connection. The OTLP endpoint is one more destination.
+    fn destinations(config: &Config, mut previous: Vec<Destination>) -> Vec<Destination> {
+        config
+            .destinations
+            .iter()
+            .map(|(host, port)| Destination::new(host, *port, config.protocol))
+            .chain(config.endpoint.iter().map(Destination::endpoint))
+            .map(|destination| {
+                match previous.iter().position(|old| {
+                    (&old.host, old.port, old.protocol, &old.path)
+                        == (
+                            &destination.host,
+                            destination.port,
+                            destination.protocol,
+                            &destination.path,
+                        )
+                }) {
+                    Some(i) => previous.swap_remove(i),
+                    None => destination,
+                }
+            })
+            .collect()
+    }
+
+    /// Splits a payload into datagrams of at most `max` bytes, breaking only
+    /// between lines so no metric is cut in two. A single line longer than
+    /// `max` (an Influx point with many fields) goes out on its own.
+    pub(crate) fn packets(payload: &str, max: usize) -> Vec<&str> {
+        let mut packets = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        for line in payload.split_inclusive('\n') {
+            if end > start && end + line.len() - start > max {
+                packets.push(&payload[start..end]);
+                start = end;
+            }
+            end += line.len();
+        }
+        if end > start {
+            packets.push(&payload[start..end]);
+        }
+        packets
+    }
+
+    fn send_udp(
+        udp: &mut Option<UdpSocket>,
+        bind: Option<IpAddr>,
+        address: SocketAddr,
+        payload: &str,
+        max_packet_size: usize,
+    ) -> io::Result<()> {
+        let socket = match udp.take() {
+            Some(socket) => socket,
+            None => Self::bind_udp(bind, address.is_ipv6())?,
+        };
+        for packet in Self::packets(payload, max_packet_size) {
+            socket.send_to(packet.as_bytes(), address)?;
+        }
+        *udp = Some(socket);
+        Ok(())
+    }
+
+    /// A missing socket (the agent isn't running yet) fails the send and is
+    /// tried again next interval like any other
+    fn send_unix(
+        unix: &mut Option<UnixDatagram>,
+        path: &str,
+        payload: &str,
+        max_packet_size: usize,
+    ) -> io::Result<()> {
+        let socket = match unix.take() {
+            Some(socket) => socket,
+            None => Self::unbound_unix()?,
+        };
+        let sent = Self::packets(payload, max_packet_size)
+            .into_iter()
+            .try_for_each(|packet| socket.send_to(packet.as_bytes(), path).map(|_| ()));
+        *unix = Some(socket);
+        sent
+    }
+
+    /// Each line is a message of its own, syslog daemons cut longer ones
+    /// off at a few kilobytes. Nothing is buffered, the log daemon is local
+    /// and a failure here is the daemon being restarted.
+    fn send_syslog(
+        unix: &mut Option<UnixDatagram>,
+        syslog: &Syslog,
+        payload: &str,
+    ) -> io::Result<()> {
+        let socket = match unix.take() {
+            Some(socket) => socket,
+            None => Self::unbound_unix()?,
+        };
+        let sent = payload.lines().try_for_each(|line| {
+            socket
+                .send_to(syslog.message(line).as_bytes(), &syslog.socket)
+                .map(|_| ())
+        });
+        *unix = Some(socket);
+        sent
+    }
+
+    fn unbound_unix() -> io::Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_write_timeout(Some(TCP_TIMEOUT))?;
+        Ok(socket)
+    }
+
+    /// From `bind` when one's given, which a destination of the other
+    /// address family can't be sent to
+    fn bind_udp(bind: Option<IpAddr>, ipv6: bool) -> io::Result<UdpSocket> {
+        match (bind, ipv6) {
+            (Some(bind), _) => UdpSocket::bind((bind, 0)),
+            (None, true) => UdpSocket::bind("[::]:0"),
+            (None, false) => UdpSocket::bind("0.0.0.0:0"),
+        }
+    }
+
+    /// Sends each destination's backlog oldest first, stopping at the first
+    /// sample that fails. `fresh` is how many of the newest weren't
+    /// buffered. Whether anything went out, and the last error.
+    fn send_backlogs(&mut self, fresh: usize) -> (bool, Option<io::Error>) {
+        let mut last_err = None;
+        let mut any_sent = false;
+        for destination in &mut self.destinations {
+            let address = destination.address();
+            let buffered = destination.backlog.len() - fresh;
+            let mut failed = None;
+            while let Some(sample) = destination.backlog.pop_front() {
+                let mut send = |destination: &mut Destination| match destination.protocol {
+                    Protocol::Udp => destination.resolve(self.resolve_ttl).and_then(|addr| {
+                        let udp = match addr.is_ipv6() {
+                            true => &mut self.udp6,
+                            false => &mut self.udp,
+                        };
+                        Self::send_udp(udp, self.bind, addr, &sample, self.max_packet_size)
+                    }),
+                    Protocol::Tcp => destination
+                        .resolve(self.resolve_ttl)
+                        .and_then(|addr| destination.send_tcp(addr, sample.as_bytes())),
+                    Protocol::Http => destination
+                        .resolve(self.resolve_ttl)
+                        .and_then(|addr| destination.send_http(addr, &sample)),
+                    Protocol::Unix if self.collectd => destination.send_collectd(&sample),
+                    Protocol::Unix => Self::send_unix(
+                        &mut self.unix,
+                        &destination.host,
+                        &sample,
+                        self.max_packet_size,
+                    ),
+                };
+                let mut sent = send(destination);
+                let mut backoff = RETRY_BACKOFF;
+                for retry in 1..=self.send_retries {
+                    let Err(err) = &sent else {
+                        break;
+                    };
+                    debug!("Send to {address} failed, retry {retry} in {backoff:?}: {err}");
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                    sent = send(destination);
+                }
+                match sent {
+                    Ok(()) => debug!("Sent {} bytes to {address}", sample.len()),
+                    Err(err) => {
+                        destination.backlog.push_front(sample);
+                        failed = Some(err);
+                        break;
+                    }
+                }
+            }
+            match failed {
+                None => {
+                    if buffered > 0 {
+                        info!("Sent {buffered} buffered samples to {address}");
+                    }
+                    any_sent = true;
+                }
+                Some(err) => {
+                    // The server may have moved, look it up again next time
+                    destination.resolved = None;
+                    while destination.backlog.len() > self.buffer_size {
+                        destination.backlog.pop_front();
+                    }
+                    let retry = match fresh {
+                        0 => "",
+                        _ => ", will retry next interval",
+                    };
+                    error!("Unable to send to {address}{retry}: {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        (any_sent, last_err)
+    }
+
+    /// One last try at the buffered samples, when exiting. Whatever still
+    /// doesn't go out is lost, and said so.
+    pub fn flush(&mut self) {
+        if self
+            .destinations
+            .iter()
+            .all(|destination| destination.backlog.is_empty())
+        {
+            return;
+        }
+        self.send_backlogs(0);
+        for destination in &self.destinations {
+            if !destination.backlog.is_empty() {
+                warn!(
+                    "Dropping {} unsent samples for {}",
+                    destination.backlog.len(),
+                    destination.address()
+                );
+            }
+        }
+    }
+}
+
+impl MetricSink for NetworkSink {
+    /// Every destination gets the same payload, one failing doesn't stop
+    /// the others. Fails only when the sample went out to none of them.
+    ///
+    /// With `--buffer-size` a destination that can't be reached keeps its
+    /// unsent samples, dropping the oldest when full, and they go out in
+    /// order ahead of the next sample that gets through. Influx and Graphite
+    /// payloads carry their collection time so they land where they belong.
+    /// A sample split over several datagrams that fails partway is resent
+    /// whole.
+    fn emit(&mut self, metrics: &Metrics) -> io::Result<()> {
+        if !self.encoder.due(metrics) {
+            debug!("Skipping sample {}, --sample-rate", metrics.sends);
+            return Ok(());
+        }
+        let payload = self.encoder.encode(metrics);
+        for destination in &mut self.destinations {
+            destination.backlog.push_back(payload.clone());
+        }
+        let (mut any_sent, mut last_err) = self.send_backlogs(1);
+        if let Some(syslog) = &self.syslog {
+            match Self::send_syslog(&mut self.unix, syslog, &payload) {
+                Ok(()) => {
+                    debug!("Logged {} bytes to syslog", payload.len());
+                    any_sent = true;
+                }
+                Err(err) => {
+                    error!("Unable to write to syslog at {}: {err}", syslog.socket);
+                    last_err = Some(err);
+                }
+            }
+        }
+        if let Some(file) = &mut self.output_file {
+            match file.write(&payload) {
+                Ok(()) => {
+                    debug!("Wrote {} bytes to {}", payload.len(), file.path);
+                    any_sent = true;
+                }
+                Err(err) => {
+                    error!("Unable to write to {}: {err}", file.path);
+                    last_err = Some(err);
+                }
+            }
+        }
+        match (any_sent, last_err) {
+            (false, Some(err)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        SysInfo,
+        testing::{self, TempDir},
+    };
+    use std::{net::TcpListener, os::unix::net::UnixListener};
+
+    /// A sink for `config` and the host it samples
+    fn sink(config: &Config) -> (SysInfo, NetworkSink) {
+        let info = SysInfo::new(config);
+        let sink = NetworkSink::new(config, Encoder::new(config, info.hostname()));
+        (info, sink)
+    }
+
+    fn recv(socket: &UdpSocket) -> String {
+        let mut buf = [0; 65536];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn resolve() {
+        let mut destination = Destination::new("localhost", 8125, Protocol::Udp);
+        let ttl = Duration::from_secs(300);
+        let addr = destination.resolve(ttl).unwrap();
+        assert!(addr.ip().is_loopback() && addr.port() == 8125, "{addr}");
+        let (_, at) = destination.resolved.unwrap();
+        // Cached while it's younger than the TTL
+        assert_eq!(destination.resolve(ttl).unwrap(), addr);
+        assert_eq!(destination.resolved.unwrap().1, at);
+        // Looked up again once it's older
+        destination.resolve(Duration::ZERO).unwrap();
+        assert!(destination.resolved.unwrap().1 > at);
+        // The name stops resolving, the last address is better than nothing
+        destination.host = "nonexistent.invalid".to_string();
+        assert_eq!(destination.resolve(Duration::ZERO).unwrap(), addr);
+        destination.resolved = None;
+        assert!(destination.resolve(Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn udp_v4_and_v6() {
+        let v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let v6 = UdpSocket::bind("[::1]:0").unwrap();
+        let destinations = format!(
+            "127.0.0.1:{},[::1]:{}",
+            v4.local_addr().unwrap().port(),
+            v6.local_addr().unwrap().port()
+        );
+        let config = testing::config(&[&destinations, "myapp", "/", "lo"]);
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        for socket in [v4, v6] {
+            let payload = recv(&socket);
+            assert!(payload.starts_with("myapp."), "{payload}");
+        }
+    }
+
+    #[test]
+    fn tcp_reconnects() {
+        // Hangs up on the first connection after one sample, like a
+        // restarted server, and hands back the sample on the next
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut samples = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut sample = Vec::new();
+                let mut buf = [0; 4096];
+                while !sample.ends_with(b"\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    sample.extend_from_slice(&buf[..n]);
+                }
+                samples.push(String::from_utf8(sample).unwrap());
+            }
+            samples
+        });
+        let config = testing::config(&[
+            "--protocol",
+            "tcp",
+            "--metrics",
+            "uptime",
+            &destination,
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        // A write or two may still go out on the dead connection before
+        // the reset comes back
+        while !server.is_finished() {
+            sink.emit(&info.sample()).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        for sample in server.join().unwrap() {
+            assert!(sample.starts_with("myapp.") && sample.contains(".uptime:"));
+        }
+    }
+
+    #[test]
+    fn fan_out() {
+        let dir = TempDir::new("fan-out");
+        let missing = dir.join("down.sock");
+        let primary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let destinations = format!(
+            "{missing},{},{}",
+            primary.local_addr().unwrap(),
+            secondary.local_addr().unwrap()
+        );
+        let config = testing::config(&["--metrics", "uptime", &destinations, "myapp", "/", "lo"]);
+        let (mut info, mut sink) = sink(&config);
+        // The first destination failing doesn't stop the others
+        sink.emit(&info.sample()).unwrap();
+        assert_eq!(recv(&primary), recv(&secondary));
+        // Only none of them getting it fails the send
+        let config = testing::config(&["--metrics", "uptime", &missing, "myapp", "/", "lo"]);
+        let (mut info, mut unreachable) = self::sink(&config);
+        assert!(unreachable.emit(&info.sample()).is_err());
+    }
+
+    #[test]
+    fn udp_socket_reused() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let destination = server.local_addr().unwrap().to_string();
+        let config = testing::config(&["--metrics", "uptime", &destination, "myapp", "/", "lo"]);
+        let (mut info, mut sink) = sink(&config);
+        let mut buf = [0; 1400];
+        let mut sources = Vec::new();
+        for _ in 0..3 {
+            sink.emit(&info.sample()).unwrap();
+            sources.push(server.recv_from(&mut buf).unwrap().1);
+        }
+        assert!(
+            sources.iter().all(|&source| source == sources[0]),
+            "{sources:?}"
+        );
+    }
+
+    #[test]
+    fn bind() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let destination = server.local_addr().unwrap().to_string();
+        let config = testing::config(&[
+            "--bind",
+            "127.0.0.1",
+            "--metrics",
+            "uptime",
+            &destination,
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        let mut buf = [0; 1400];
+        let (n, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(from.ip().to_string(), "127.0.0.1");
+        assert!(String::from_utf8_lossy(&buf[..n]).starts_with("myapp."));
+    }
+
+    #[test]
+    fn unix_datagram() {
+        let dir = TempDir::new("unix");
+        let path = dir.join("statsd.sock");
+        let config = testing::config(&["--metrics", "uptime", &path, "myapp", "/", "lo"]);
+        let (mut info, mut sink) = sink(&config);
+        // The agent isn't running yet, that sample is lost and the next
+        // one tried as usual
+        assert!(sink.emit(&info.sample()).is_err());
+        let agent = UnixDatagram::bind(&path).unwrap();
+        sink.emit(&info.sample()).unwrap();
+        let mut buf = [0; 2048];
+        let len = agent.recv(&mut buf).unwrap();
+        let payload = String::from_utf8_lossy(&buf[..len]).into_owned();
+        assert!(
+            payload.starts_with("myapp.") && payload.contains(".uptime:"),
+            "{payload}"
+        );
+        assert_eq!(sink.addresses(), [path]);
+    }
+
+    #[test]
+    fn collectd_unixsock() {
+        let dir = TempDir::new("collectd");
+        let path = dir.join("collectd-unixsock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let config = testing::config(&[
+            "--format",
+            "collectd",
+            "--metrics",
+            "uptime,sends",
+            &path,
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        // Answers like the plugin, refusing the second sample's sends
+        let collectd = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut lines = Vec::new();
+            for reply in ["0 Success", "0 Success", "0 Success", "-1 Unknown type"] {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                lines.push(line);
+                writeln!(stream, "{reply}").unwrap();
+            }
+            lines
+        });
+        sink.emit(&info.sample()).unwrap();
+        // The refused value is dropped, the one before it isn't sent again
+        sink.emit(&info.sample()).unwrap();
+        assert!(sink.destinations[0].backlog.is_empty());
+        let lines = collectd.join().unwrap();
+        assert!(lines[0].contains("/uptime/uptime\""), "{}", lines[0]);
+        assert!(lines[1].ends_with(" N:1\n"), "{}", lines[1]);
+        assert!(lines[3].ends_with(" N:2\n"), "{}", lines[3]);
+    }
+
+    #[test]
+    fn buffer_replay() {
+        let dir = TempDir::new("buffer");
+        let path = dir.join("agent.sock");
+        let config = testing::config(&[
+            "--buffer-size",
+            "2",
+            "--format",
+            "json",
+            "--metrics",
+            "sends",
+            &path,
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        for _ in 0..3 {
+            assert!(sink.emit(&info.sample()).is_err());
+        }
+        // The agent comes up, the two newest buffered samples go out in
+        // order ahead of the new one and the oldest was dropped
+        let agent = UnixDatagram::bind(&path).unwrap();
+        sink.emit(&info.sample()).unwrap();
+        let mut buf = [0; 2048];
+        let sends: Vec<String> = (0..3)
+            .map(|_| {
+                let len = agent.recv(&mut buf).unwrap();
+                let json = String::from_utf8_lossy(&buf[..len]).into_owned();
+                json.split("\"sends\":")
+                    .nth(1)
+                    .unwrap()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(sends, ["2}", "3}", "4}"]);
+        // Nothing is left to replay
+        sink.emit(&info.sample()).unwrap();
+        let len = agent.recv(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..len]).contains("\"sends\":5}"));
+        // Exiting gives what's buffered one more try
+        drop(agent);
+        fs::remove_file(&path).unwrap();
+        for _ in 0..2 {
+            assert!(sink.emit(&info.sample()).is_err());
+        }
+        let agent = UnixDatagram::bind(&path).unwrap();
+        sink.flush();
+        let len = agent.recv(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..len]).contains("\"sends\":6}"));
+        let len = agent.recv(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..len]).contains("\"sends\":7}"));
+        assert!(sink.destinations[0].backlog.is_empty());
+    }
+
+    #[test]
+    fn send_retries() {
+        // The agent isn't listening yet when the first send goes out
+        let dir = TempDir::new("retry");
+        let path = dir.join("agent.sock");
+        let agent = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                let socket = UnixDatagram::bind(&path).unwrap();
+                let mut buf = [0; 2048];
+                let len = socket.recv(&mut buf).unwrap();
+                String::from_utf8_lossy(&buf[..len]).into_owned()
+            })
+        };
+        let config = testing::config(&[
+            "--send-retries",
+            "3",
+            "--metrics",
+            "uptime",
+            &path,
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        assert!(agent.join().unwrap().contains(".uptime:"));
+    }
+
+    #[test]
+    fn output_file() {
+        let dir = TempDir::new("output");
+        let path = dir.join("uptimed.metrics");
+        let config = testing::config(&[
+            "--output-file",
+            &path,
+            "--metrics",
+            "uptime",
+            "--namespace",
+            "myapp",
+            "--filesystem",
+            "/",
+            "--interface",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        sink.emit(&info.sample()).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("# ") && lines[2].starts_with("# "));
+        assert!(lines[1].starts_with("myapp.") && lines[1].contains(".uptime:"));
+
+        // logrotate moves the file away, the next sample starts a new one
+        fs::rename(&path, dir.join("uptimed.metrics.1")).unwrap();
+        sink.emit(&info.sample()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        assert_eq!(
+            fs::read_to_string(dir.join("uptimed.metrics.1")).unwrap(),
+            written
+        );
+    }
+
+    #[test]
+    fn syslog() {
+        let dir = TempDir::new("syslog");
+        let path = dir.join("log");
+        let log = UnixDatagram::bind(&path).unwrap();
+        let config = testing::config(&[
+            "--syslog",
+            "--syslog-socket",
+            &path,
+            "--syslog-facility",
+            "local3",
+            "--metrics",
+            "uptime",
+            "--namespace",
+            "myapp",
+            "--filesystem",
+            "/",
+            "--interface",
+            "lo",
+        ]);
+        assert!(config.destinations.is_empty());
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        let mut buf = [0; 2048];
+        let len = log.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]).into_owned();
+        // local3 is facility 19, info severity 6
+        let header = format!("<158>uptimed[{}]: myapp.", std::process::id());
+        assert!(message.starts_with(&header), "{message}");
+    }
+
+    #[test]
+    fn otlp() {
+        // Answers each request with the next status, handing back what was
+        // sent
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let collector = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .unwrap();
+                        if body.len() >= length.parse().unwrap() {
+                            requests.push(text);
+                            break;
+                        }
+                    }
+                }
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        let config = testing::config(&[
+            "--format",
+            "otlp",
+            "--endpoint",
+            &url,
+            "--namespace",
+            "myapp",
+            "--filesystem",
+            "/",
+            "--interface",
+            "lo",
+        ]);
+        let (mut info, mut sink) = sink(&config);
+        sink.emit(&info.sample()).unwrap();
+        assert!(sink.emit(&info.sample()).is_err());
+        let requests = collector.join().unwrap();
+        assert!(requests[0].starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        for name in [
+            "myapp.uptime",
+            "myapp.availmem",
+            "myapp.load",
+            "myapp.cpu",
+            "myapp.sends",
+        ] {
+            assert!(
+                requests[0].contains(&format!("{{\"name\":\"{name}\",")),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let payload: String = (0..500)
+            .map(|i| format!("myapp.host.metric-{i}:{i}|g\n"))
+            .collect();
+        let packets = NetworkSink::packets(&payload, 512);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|packet| packet.len() <= 512));
+        assert!(packets.iter().all(|packet| packet.ends_with('\n')));
+        assert_eq!(packets.concat(), payload);
+    }
+}
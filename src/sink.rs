@@ -0,0 +1,60 @@
+use crate::{Encoder, Metrics};
+use std::io::{self, Write};
+
+/// Somewhere samples go. `main` picks one at startup and hands it every
+/// sample, so a new backend only needs an impl of this.
+///
+/// ```
+/// use std::io;
+/// use uptimed::{Config, MetricSink, Metrics, SysInfo};
+///
+/// /// Keeps every sample in memory
+/// struct Captured(Vec<Metrics>);
+///
+/// impl MetricSink for Captured {
+///     fn emit(&mut self, metrics: &Metrics) -> io::Result<()> {
+///         self.0.push(metrics.clone());
+///         Ok(())
+///     }
+/// }
+///
+/// let args: Vec<String> = ["localhost", "myapp", "/", "lo"]
+///     .iter()
+///     .map(|arg| arg.to_string())
+///     .collect();
+/// let config = Config::from_args(&args)?;
+/// let mut info = SysInfo::new(&config);
+/// let mut sink = Captured(Vec::new());
+/// sink.emit(&info.sample())?;
+/// info.refresh();
+/// sink.emit(&info.sample())?;
+/// assert_eq!(sink.0.len(), 2);
+/// assert_eq!(sink.0[1].sends, 2);
+/// assert_eq!(sink.0[1].filesystems[0].path, "/");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub trait MetricSink {
+    fn emit(&mut self, metrics: &Metrics) -> io::Result<()>;
+}
+
+/// Writes each sample to stdout in the configured push format, for `--once`
+pub struct Stdout {
+    encoder: Encoder,
+}
+
+impl Stdout {
+    pub fn new(encoder: Encoder) -> Self {
+        Self { encoder }
+    }
+}
+
+impl MetricSink for Stdout {
+    fn emit(&mut self, metrics: &Metrics) -> io::Result<()> {
+        if !self.encoder.due(metrics) {
+            return Ok(());
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.encoder.encode(metrics).as_bytes())?;
+        stdout.flush()
+    }
+}
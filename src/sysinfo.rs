@@ -0,0 +1,1386 @@
+use crate::{
+    Alert, CollectError, Config, FsStats, MemInfo, Mount,
+    collect::{
+        Disk, Filesystem, Interface, InterfaceErrors, MEM_BREAKDOWN, NET_ERRORS, host_path,
+        parse_float, read_file,
+    },
+    collectors::{self, Collector},
+    metrics::METRICS,
+};
+use libc::{self};
+use std::{
+    ffi::{CStr, CString},
+    fs, io, mem,
+    path::Path,
+    ptr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// ARPHRD_LOOPBACK, the link type in /sys/class/net/<interface>/type
+const LOOPBACK_TYPE: &str = "772";
+
+/// The hwmon chip names of drives, NVMe controllers and the SATA and SAS
+/// disks the drivetemp driver covers
+const DRIVE_SENSORS: [&str; 2] = ["nvme", "drivetemp"];
+
+/// Where the machine ID is kept, systemd's file and then the older D-Bus one
+const MACHINE_ID_PATHS: [&str; 2] = ["/etc/machine-id", "/var/lib/dbus/machine-id"];
+
+/// The latest sample for this host
+pub struct SysInfo {
+    pub(crate) hostname: String,
+    /// The collectors `--disable` left, in the order they're refreshed
+    pub(crate) collectors: Vec<Box<dyn Collector>>,
+    /// How long the last refresh took
+    pub(crate) collect_ms: u64,
+    /// Samples sent, or attempted, since startup
+    pub(crate) sends: u64,
+    /// When a sample last reached every destination
+    pub(crate) last_sent: Option<Instant>,
+    /// `config.metrics`, or `None` when that's every metric
+    pub(crate) enabled: Option<Vec<String>>,
+    /// Send availmem, the memory breakdown and diskfree in bytes rather than
+    /// percent
+    pub(crate) absolute: bool,
+    /// Decimal places the percentages and load are sent with
+    pub(crate) precision: u32,
+    pub(crate) alerts: Vec<Alert>,
+}
+
+impl SysInfo {
+    /// Collectors named in `config.disabled` are never run and their
+    /// metrics are left out of every format.
+    pub fn new(config: &Config) -> Self {
+        let hostname = match config.local_hostname {
+            true => Self::local_hostname().or_else(|err| {
+                warn!("Unable to get the local hostname, reading /proc instead: {err}");
+                Self::get_hostname()
+            }),
+            false => Self::get_hostname(),
+        };
+        let hostname = hostname.unwrap_or_else(|err| {
+            warn!("Unable to collect hostname, using unknown: {err}");
+            "unknown".to_string()
+        });
+        let hostname = match config.fqdn {
+            true => Self::fqdn(&hostname),
+            false => hostname,
+        };
+        Self {
+            hostname,
+            collectors: collectors::collectors(config, Vec::new()),
+            collect_ms: 0u64,
+            sends: 0u64,
+            last_sent: None,
+            enabled: Self::enabled(config),
+            absolute: config.absolute,
+            precision: config.precision,
+            alerts: config.alerts.clone(),
+        }
+    }
+
+    /// Applies a reloaded configuration. Collectors that are still enabled
+    /// keep their counters so the next sample's deltas stay correct.
+    pub fn reload(&mut self, config: &Config) {
+        self.collectors = collectors::collectors(config, mem::take(&mut self.collectors));
+        self.enabled = Self::enabled(config);
+        self.absolute = config.absolute;
+        self.precision = config.precision;
+        self.alerts = config.alerts.clone();
+    }
+
+    pub fn refresh(&mut self) {
+        let started = Instant::now();
+        for collector in &mut self.collectors {
+            collector.collect();
+        }
+        self.collect_ms = started.elapsed().as_millis() as u64;
+    }
+
+    fn enabled(config: &Config) -> Option<Vec<String>> {
+        match config.metrics.len() == METRICS.len() {
+            true => None,
+            false => Some(config.metrics.clone()),
+        }
+    }
+
+    /// A metric that can't be collected keeps its last value, a hiccup
+    /// reading /proc shouldn't take the daemon down
+    pub(crate) fn update<T>(metric: &str, value: &mut T, collected: Result<T, CollectError>) {
+        match collected {
+            Ok(collected) => *value = collected,
+            Err(err) => Self::collect_failed(metric, err),
+        }
+    }
+
+    /// With no last value to keep, a metric that can't be collected at
+    /// startup begins at 0
+    pub(crate) fn initial<T: Default>(metric: &str, collected: Result<T, CollectError>) -> T {
+        collected.unwrap_or_else(|err| {
+            Self::collect_failed(metric, err);
+            T::default()
+        })
+    }
+
+    pub(crate) fn collect_failed(metric: &str, err: CollectError) {
+        warn!("Unable to collect {metric}, keeping its last value: {err}");
+    }
+
+    /// Interfaces that don't exist at startup are skipped with a warning.
+    /// One that disappears later reports 0 until it comes back.
+    pub(crate) fn interfaces(names: &[String]) -> Vec<Interface> {
+        Self::watched_interfaces(names)
+            .map(|(name, suffix)| Interface::new(&name, suffix))
+            .collect()
+    }
+
+    pub(crate) fn interface_errors(names: &[String]) -> Vec<InterfaceErrors> {
+        Self::watched_interfaces(names)
+            .map(|(name, suffix)| InterfaceErrors::new(&name, suffix))
+            .collect()
+    }
+
+    /// The interfaces that exist, with the suffix their metrics are sent with
+    pub(crate) fn watched_interfaces(names: &[String]) -> impl Iterator<Item = (String, String)> {
+        let names: Vec<String> = Self::interface_paths(names)
+            .into_iter()
+            .filter(|name| {
+                let exists = Path::new(&host_path(&format!("/sys/class/net/{name}"))).exists();
+                if !exists {
+                    warn!("Network interface {name} does not exist, skipping it");
+                }
+                exists
+            })
+            .collect();
+        let several = names.len() > 1;
+        names.into_iter().map(move |name| {
+            let suffix = match several {
+                true => format!(".{name}"),
+                false => String::new(),
+            };
+            (name, suffix)
+        })
+    }
+
+    /// `names` with `auto` replaced by every interface there is now but
+    /// loopback
+    fn interface_paths(names: &[String]) -> Vec<String> {
+        let mut interfaces: Vec<String> = Vec::new();
+        for name in names {
+            let found = match name.as_str() {
+                "auto" => {
+                    Self::discover_interfaces(&host_path("/sys/class/net")).unwrap_or_else(|err| {
+                        warn!("Unable to discover network interfaces: {err}");
+                        Vec::new()
+                    })
+                }
+                _ => vec![name.clone()],
+            };
+            for interface in found {
+                if !interfaces.contains(&interface) {
+                    interfaces.push(interface);
+                }
+            }
+        }
+        interfaces
+    }
+
+    /// The interfaces in `class_net`, sorted, leaving out loopback. It's
+    /// told apart by its link type rather than by being called `lo`.
+    pub(crate) fn discover_interfaces(class_net: &str) -> Result<Vec<String>, CollectError> {
+        let entries = fs::read_dir(class_net)
+            .map_err(|err| CollectError::Read(class_net.to_string(), err))?;
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|entry| {
+                fs::read_to_string(entry.path().join("type"))
+                    .map_or(true, |link_type| link_type.trim() != LOOPBACK_TYPE)
+            })
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Block devices, partitions included, are all listed in /sys/class/block
+    pub(crate) fn disks(names: &[String]) -> Vec<Disk> {
+        names
+            .iter()
+            .filter(|name| {
+                let exists = Path::new(&host_path(&format!("/sys/class/block/{name}"))).exists();
+                if !exists {
+                    warn!("Block device {name} does not exist, skipping it");
+                }
+                exists
+            })
+            .map(|name| Disk::new(name))
+            .collect()
+    }
+
+    /// `config.filesystems` with `auto` replaced by every real filesystem
+    /// mounted now
+    pub(crate) fn filesystem_paths(config: &Config) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+        for filesystem in &config.filesystems {
+            let found = match filesystem.as_str() {
+                "auto" => match Mount::read() {
+                    Ok(mounts) => {
+                        Mount::discover(&mounts, &config.include_fs_types, &config.exclude_fs_types)
+                    }
+                    Err(err) => {
+                        warn!("Unable to discover filesystems: {err}");
+                        Vec::new()
+                    }
+                },
+                _ => vec![filesystem.clone()],
+            };
+            for path in found {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        paths
+    }
+
+    /// Filesystems that can't be accessed at startup are skipped with a
+    /// warning, and the suffix only goes on when several are left, as for
+    /// interfaces
+    pub(crate) fn filesystems(paths: &[String]) -> Vec<Filesystem> {
+        let paths: Vec<&String> = paths
+            .iter()
+            .filter(|path| match fs::metadata(path) {
+                Ok(_) => true,
+                Err(err) => {
+                    warn!("Unable to access filesystem {path}, skipping it: {err}");
+                    false
+                }
+            })
+            .collect();
+        let several = paths.len() > 1;
+        paths
+            .into_iter()
+            .map(|path| {
+                let suffix = match several {
+                    true => format!(".{}", Filesystem::metric_name(path)),
+                    false => String::new(),
+                };
+                Filesystem::new(path, suffix)
+            })
+            .collect()
+    }
+
+    /// The hostname read at startup
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// The hostname of the host whose /proc we read, which is a container's
+    /// host with `--proc-root`
+    pub fn get_hostname() -> Result<String, CollectError> {
+        let path = "/proc/sys/kernel/hostname";
+        Self::parse_hostname(&read_file(path)?).ok_or(CollectError::Parse(path.to_string()))
+    }
+
+    /// The first line, trimmed. An empty name would leave a trailing dot in
+    /// the prefix, so it's `None` for the caller to fall back from.
+    pub(crate) fn parse_hostname(contents: &str) -> Option<String> {
+        let hostname = contents.lines().next()?.trim();
+        (!hostname.is_empty()).then(|| hostname.to_string())
+    }
+
+    /// Our own hostname from gethostname(2), whatever /proc we read
+    pub fn local_hostname() -> Result<String, CollectError> {
+        // HOST_NAME_MAX is 64 on Linux, the name may not be NUL terminated
+        // if it fills the buffer
+        let mut buf = [0u8; 256];
+        let res = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) };
+        if res != 0 {
+            return Err(CollectError::Read(
+                "gethostname()".to_string(),
+                io::Error::last_os_error(),
+            ));
+        }
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        Self::parse_hostname(&String::from_utf8_lossy(&buf[..len]))
+            .ok_or(CollectError::Parse("gethostname()".to_string()))
+    }
+
+    /// The canonical name the resolver has for this host. Without one a
+    /// short name gets the kernel's NIS domain name appended, and without
+    /// that it's left as it is.
+    pub fn fqdn(hostname: &str) -> String {
+        Self::canonical_name(hostname)
+            .or_else(|| {
+                if hostname.contains('.') {
+                    return None;
+                }
+                let domain = read_file("/proc/sys/kernel/domainname").ok()?;
+                let domain = domain.trim();
+                (!domain.is_empty() && domain != "(none)").then(|| format!("{hostname}.{domain}"))
+            })
+            .unwrap_or_else(|| hostname.to_string())
+    }
+
+    fn canonical_name(hostname: &str) -> Option<String> {
+        let name = CString::new(hostname).ok()?;
+        unsafe {
+            let mut hints: libc::addrinfo = mem::zeroed();
+            hints.ai_flags = libc::AI_CANONNAME;
+            let mut result = ptr::null_mut();
+            if libc::getaddrinfo(name.as_ptr(), ptr::null(), &hints, &mut result) != 0 {
+                return None;
+            }
+            let canonical = (*result).ai_canonname;
+            let fqdn = match canonical.is_null() {
+                true => None,
+                false => Some(CStr::from_ptr(canonical).to_string_lossy().into_owned()),
+            };
+            libc::freeaddrinfo(result);
+            fqdn
+        }
+    }
+
+    /// The ID generated when the system was installed, which stays the same
+    /// when the hostname is reused or changed. Minimal containers often have
+    /// neither file, leaving `None`.
+    pub fn machine_id() -> Option<String> {
+        MACHINE_ID_PATHS
+            .iter()
+            .find_map(|path| Self::read_machine_id(path))
+    }
+
+    /// The ID in a machine-id file, 32 hex digits on one line. A file that's
+    /// missing, empty or still `uninitialized` from first boot has none.
+    pub(crate) fn read_machine_id(path: &str) -> Option<String> {
+        let id = fs::read_to_string(path).ok()?;
+        let id = id.trim();
+        (id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+            .then(|| id.to_ascii_lowercase())
+    }
+
+    /// Every network interface in /sys/class/net, sorted
+    pub fn interface_names() -> Result<Vec<String>, CollectError> {
+        let path = host_path("/sys/class/net");
+        let entries = fs::read_dir(&path).map_err(|err| CollectError::Read(path, err))?;
+        let mut names: Vec<String> = entries
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// The interface's operstate, `up`, `down`, `unknown` (loopback and many
+    /// virtual interfaces) and so on
+    pub fn operstate(interface: &str) -> Result<String, CollectError> {
+        Ok(read_file(&format!("/sys/class/net/{interface}/operstate"))?
+            .trim()
+            .to_string())
+    }
+
+    /// Whether the interface's link is up, and its speed in Mbps if it has
+    /// one
+    pub fn link(interface: &str) -> Result<(bool, Option<u64>), CollectError> {
+        Self::read_link(&host_path(&format!("/sys/class/net/{interface}")))
+    }
+
+    /// Reads `operstate` and `speed` from an interface's directory. Drivers
+    /// that don't track operstate say `unknown`, loopback among them, and
+    /// then `carrier` decides. Virtual interfaces have no speed, reading it
+    /// fails or gives -1.
+    pub(crate) fn read_link(dir: &str) -> Result<(bool, Option<u64>), CollectError> {
+        let read = |file: &str| {
+            let path = format!("{dir}/{file}");
+            fs::read_to_string(&path)
+                .map(|contents| contents.trim().to_string())
+                .map_err(|err| CollectError::Read(path, err))
+        };
+        let up = match read("operstate")?.as_str() {
+            "up" => true,
+            "unknown" => read("carrier").is_ok_and(|carrier| carrier == "1"),
+            _ => false,
+        };
+        let speed = read("speed")
+            .ok()
+            .and_then(|speed| speed.parse::<i64>().ok())
+            .and_then(|speed| u64::try_from(speed).ok());
+        Ok((up, speed))
+    }
+
+    pub fn net_stats(interface: &str, kind: &str) -> Result<u64, CollectError> {
+        let path = format!("/sys/class/net/{interface}/statistics/{kind}x_bytes");
+        read_file(&path)?
+            .trim()
+            .parse()
+            .map_err(|_| CollectError::Parse(path))
+    }
+
+    /// The interface's rx_errors, tx_errors, rx_dropped and tx_dropped
+    /// counters
+    pub fn net_errors(interface: &str) -> Result<[u64; 4], CollectError> {
+        let mut counters = [0; 4];
+        for (counter, (file, _)) in counters.iter_mut().zip(NET_ERRORS) {
+            let path = format!("/sys/class/net/{interface}/statistics/{file}");
+            *counter = read_file(&path)?
+                .trim()
+                .parse()
+                .map_err(|_| CollectError::Parse(path))?;
+        }
+        Ok(counters)
+    }
+
+    /// How far each counter moved since `previous`. One that went backwards,
+    /// as they do when a driver resets, counts 0 rather than underflowing.
+    pub(crate) fn deltas<const N: usize>(current: [u64; N], previous: [u64; N]) -> [u64; N] {
+        let mut deltas = current;
+        for (delta, previous) in deltas.iter_mut().zip(previous) {
+            *delta = delta.saturating_sub(previous);
+        }
+        deltas
+    }
+
+    pub fn uptime() -> Result<f32, CollectError> {
+        Self::parse_uptime(&read_file("/proc/uptime")?)
+            .ok_or(CollectError::Parse("/proc/uptime".to_string()))
+    }
+
+    /// The first of /proc/uptime's two numbers, rounded to whole seconds
+    pub(crate) fn parse_uptime(contents: &str) -> Option<f32> {
+        contents
+            .split_whitespace()
+            .next()
+            .and_then(parse_float)
+            .map(|uptime| uptime.round() as f32)
+    }
+
+    /// Unix time of boot, it only changes with a reboot
+    pub fn boot_time() -> Result<u64, CollectError> {
+        Self::parse_btime(&read_file("/proc/stat")?)
+            .ok_or(CollectError::Parse("/proc/stat".to_string()))
+    }
+
+    /// The `btime` line of /proc/stat
+    pub(crate) fn parse_btime(stat: &str) -> Option<u64> {
+        stat.lines()
+            .find_map(|line| line.strip_prefix("btime "))?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// A meminfo that couldn't be made sense of has no MemTotal, and gives 0
+    pub(crate) fn avail_mem(meminfo: &MemInfo) -> f64 {
+        if meminfo.total == 0 {
+            warn!("/proc/meminfo reports no MemTotal, reporting availmem as 0");
+            return 0f64;
+        }
+        meminfo.available() as f64 / meminfo.total as f64 * 100.0
+    }
+
+    /// /proc/meminfo counts in KiB
+    pub(crate) fn avail_mem_bytes(meminfo: &MemInfo) -> u64 {
+        meminfo.available() * 1024
+    }
+
+    /// Bytes of Buffers, Cached, Dirty and SReclaimable, the memory that's in
+    /// use but mostly given back under pressure
+    pub(crate) fn mem_breakdown(meminfo: &MemInfo) -> [u64; 4] {
+        Self::mem_breakdown_kib(meminfo).map(|kib| kib * 1024)
+    }
+
+    /// The breakdown as percent of MemTotal, 0 without a MemTotal
+    pub(crate) fn mem_breakdown_percent(meminfo: &MemInfo) -> [f64; 4] {
+        if meminfo.total == 0 {
+            return [0f64; 4];
+        }
+        Self::mem_breakdown_kib(meminfo).map(|kib| kib as f64 / meminfo.total as f64 * 100.0)
+    }
+
+    fn mem_breakdown_kib(meminfo: &MemInfo) -> [u64; MEM_BREAKDOWN.len()] {
+        [
+            meminfo.buffers,
+            meminfo.cached,
+            meminfo.dirty,
+            meminfo.s_reclaimable,
+        ]
+    }
+
+    /// Percent of swap in use, 0 when there's no swap at all
+    pub(crate) fn swap_used(meminfo: &MemInfo) -> f64 {
+        if meminfo.swap_total == 0 {
+            return 0f64;
+        }
+        let used = meminfo.swap_total.saturating_sub(meminfo.swap_free);
+        used as f64 / meminfo.swap_total as f64 * 100.0
+    }
+
+    /// Degrees C of the hottest thermal zone, or of `zone` when one is
+    /// chosen. Machines without thermal zones may still have hwmon sensors.
+    pub fn cpu_temp(zone: Option<u32>) -> Result<f64, CollectError> {
+        let sensors = match zone {
+            Some(zone) => vec![host_path(&format!(
+                "/sys/class/thermal/thermal_zone{zone}/temp"
+            ))],
+            None => {
+                let mut sensors = Self::sensors("/sys/class/thermal", "thermal_zone", "temp");
+                if sensors.is_empty() {
+                    sensors = Self::sensors("/sys/class/hwmon", "hwmon", "temp*_input");
+                }
+                sensors
+            }
+        };
+        Self::hottest(&sensors).ok_or(CollectError::Parse(match zone {
+            Some(zone) => format!("thermal zone {zone}"),
+            None => "/sys/class/thermal or /sys/class/hwmon, no readable sensors".to_string(),
+        }))
+    }
+
+    /// The files matching `<class>/<prefix>*/<file>`, where `file` may end
+    /// in `*_input` to match every `temp1_input`, `temp2_input`, ...
+    fn sensors(class: &str, prefix: &str, file: &str) -> Vec<String> {
+        let Ok(devices) = fs::read_dir(host_path(class)) else {
+            return Vec::new();
+        };
+        let mut sensors = Vec::new();
+        for device in devices.flatten() {
+            if !device.file_name().to_string_lossy().starts_with(prefix) {
+                continue;
+            }
+            match file.split_once('*') {
+                Some((start, end)) => {
+                    let Ok(files) = fs::read_dir(device.path()) else {
+                        continue;
+                    };
+                    sensors.extend(files.flatten().filter_map(|f| {
+                        let name = f.file_name().to_string_lossy().into_owned();
+                        (name.starts_with(start) && name.ends_with(end))
+                            .then(|| f.path().to_string_lossy().into_owned())
+                    }));
+                }
+                None => sensors.push(device.path().join(file).to_string_lossy().into_owned()),
+            }
+        }
+        sensors
+    }
+
+    /// The highest reading of the sensor files given, which hold
+    /// millidegrees C. Unreadable sensors are skipped, some zones report
+    /// errors while their device sleeps.
+    pub(crate) fn hottest(sensors: &[String]) -> Option<f64> {
+        sensors
+            .iter()
+            .filter_map(|sensor| fs::read_to_string(sensor).ok()?.trim().parse::<i64>().ok())
+            .max()
+            .map(|millidegrees| millidegrees as f64 / 1000.0)
+    }
+
+    /// Degrees C of each drive with an hwmon sensor, by device name
+    pub fn disk_temps() -> Vec<(String, f64)> {
+        Self::drive_temps(&host_path("/sys/class/hwmon"))
+    }
+
+    /// The hottest `temp*_input` of each chip in `class_hwmon` that's a
+    /// drive. NVMe sensors are named for the controller, `nvme0`, and
+    /// drivetemp ones for the block device, `sda`. Drives without a
+    /// readable sensor are left out, sorted by name.
+    pub(crate) fn drive_temps(class_hwmon: &str) -> Vec<(String, f64)> {
+        let Ok(chips) = fs::read_dir(class_hwmon) else {
+            return Vec::new();
+        };
+        let mut temps: Vec<(String, f64)> = chips
+            .flatten()
+            .filter(|chip| {
+                fs::read_to_string(chip.path().join("name"))
+                    .is_ok_and(|name| DRIVE_SENSORS.contains(&name.trim()))
+            })
+            .filter_map(|chip| {
+                let sensors: Vec<String> = fs::read_dir(chip.path())
+                    .ok()?
+                    .flatten()
+                    .filter(|f| {
+                        let name = f.file_name().to_string_lossy().into_owned();
+                        name.starts_with("temp") && name.ends_with("_input")
+                    })
+                    .map(|f| f.path().to_string_lossy().into_owned())
+                    .collect();
+                let temp = Self::hottest(&sensors)?;
+                Some((Self::drive_name(&chip.path()), temp))
+            })
+            .collect();
+        temps.sort_by(|a, b| a.0.cmp(&b.0));
+        temps
+    }
+
+    /// The block device under a drivetemp chip's device, or what its device
+    /// links to, `nvme0` for an NVMe controller. The chip's own name if
+    /// neither is there.
+    fn drive_name(chip: &Path) -> String {
+        let device = chip.join("device");
+        let block = fs::read_dir(device.join("block"))
+            .ok()
+            .and_then(|mut block| block.next()?.ok())
+            .map(|block| block.file_name());
+        let linked = || {
+            fs::read_link(&device)
+                .ok()?
+                .file_name()
+                .map(|name| name.to_owned())
+        };
+        block
+            .or_else(linked)
+            .or_else(|| chip.file_name().map(|name| name.to_owned()))
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Processes in the zombie state
+    pub fn zombies() -> Result<u64, CollectError> {
+        Self::count_zombies(&host_path("/proc"))
+    }
+
+    /// Counts the `/proc/<pid>/stat` files under `proc` whose state is `Z`.
+    /// The state follows the command name, which is in parentheses and may
+    /// hold spaces and parentheses itself, so it's found after the last `)`.
+    /// A process that exits between listing and reading is skipped.
+    pub(crate) fn count_zombies(proc: &str) -> Result<u64, CollectError> {
+        let entries =
+            fs::read_dir(proc).map_err(|err| CollectError::Read(proc.to_string(), err))?;
+        let zombies = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+            })
+            .filter_map(|entry| fs::read_to_string(entry.path().join("stat")).ok())
+            .filter(|stat| {
+                stat.rsplit_once(')')
+                    .and_then(|(_, rest)| rest.split_whitespace().next())
+                    == Some("Z")
+            })
+            .count();
+        Ok(zombies as u64)
+    }
+
+    /// The running kernel's release
+    pub fn kernel_release() -> Result<String, CollectError> {
+        let path = "/proc/version";
+        Self::parse_kernel_release(&read_file(path)?).ok_or(CollectError::Parse(path.to_string()))
+    }
+
+    /// The release from /proc/version, the word after `Linux version`
+    pub(crate) fn parse_kernel_release(contents: &str) -> Option<String> {
+        let mut words = contents.split_whitespace();
+        (words.next()? == "Linux" && words.next()? == "version").then_some(())?;
+        words.next().map(str::to_string)
+    }
+
+    /// StatsD and the other push formats only carry numbers, so the release
+    /// is sent as major * 1000000 + minor * 1000 + patch, ignoring anything
+    /// after the patch level. It reads back as the version with the dots
+    /// padded to three digits, and sorts the same way, so `kernel < 6001000`
+    /// finds every host still older than 6.1.
+    pub(crate) fn kernel_version_number(release: &str) -> Option<u64> {
+        let numeric = release
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()?;
+        let mut parts = numeric.split('.');
+        let major: u64 = parts.next()?.parse().ok()?;
+        let minor: u64 = parts.next()?.parse().ok()?;
+        let patch: u64 = match parts.next() {
+            Some(patch) => patch.parse().ok()?,
+            None => 0,
+        };
+        (minor < 1000 && patch < 1000).then_some(major * 1_000_000 + minor * 1000 + patch)
+    }
+
+    /// Bits of entropy the kernel's pool holds. Since Linux 5.18 it's always
+    /// 256, older kernels run low on idle VMs and block crypto until it
+    /// fills.
+    pub fn entropy() -> Result<u64, CollectError> {
+        let path = "/proc/sys/kernel/random/entropy_avail";
+        Self::parse_entropy(&read_file(path)?).ok_or(CollectError::Parse(path.to_string()))
+    }
+
+    pub(crate) fn parse_entropy(contents: &str) -> Option<u64> {
+        contents.trim().parse().ok()
+    }
+
+    /// Entries in the connection tracking table and its size
+    pub fn conntrack() -> Result<(u64, u64), CollectError> {
+        Self::read_conntrack(&host_path("/proc/sys/net/netfilter"))
+    }
+
+    /// Reads `nf_conntrack_count` and `nf_conntrack_max` from `dir`, which
+    /// only has them while the nf_conntrack module is loaded
+    pub(crate) fn read_conntrack(dir: &str) -> Result<(u64, u64), CollectError> {
+        let read = |file: &str| {
+            let path = format!("{dir}/{file}");
+            fs::read_to_string(&path)
+                .map_err(|err| CollectError::Read(path.clone(), err))?
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| CollectError::Parse(path))
+        };
+        Ok((read("nf_conntrack_count")?, read("nf_conntrack_max")?))
+    }
+
+    /// Percent of the table in use, 0 for a table of no size
+    pub(crate) fn conntrack_pct(count: u64, max: u64) -> f64 {
+        if max == 0 {
+            return 0f64;
+        }
+        count as f64 * 100.0 / max as f64
+    }
+
+    pub fn psi() -> Result<[f64; 3], CollectError> {
+        let mut psi = [0f64; 3];
+        for (value, resource) in psi.iter_mut().zip(["cpu", "memory", "io"]) {
+            let path = format!("/proc/pressure/{resource}");
+            *value = Self::parse_pressure(&read_file(&path)?).ok_or(CollectError::Parse(path))?;
+        }
+        Ok(psi)
+    }
+
+    /// The avg10 of the `some` line, the percent of the last 10 seconds at
+    /// least one task was stalled waiting on the resource
+    /// <https://docs.kernel.org/accounting/psi.html>
+    fn parse_pressure(contents: &str) -> Option<f64> {
+        let some = contents.lines().find(|line| line.starts_with("some "))?;
+        some.split_whitespace()
+            .find_map(|field| field.strip_prefix("avg10="))
+            .and_then(parse_float)
+    }
+
+    /// Counts the `processor` lines in /proc/cpuinfo. Some ARM kernels have
+    /// none, so then the online CPUs in /sys are counted, and then what the
+    /// scheduler lets us use. Load is never divided by zero, with no count
+    /// at all it's divided by 1.
+    pub fn cores() -> f32 {
+        let from_cpuinfo = read_file("/proc/cpuinfo")
+            .inspect_err(|err| warn!("Unable to count cores: {err}"))
+            .map(|cpuinfo| Self::count_processors(&cpuinfo))
+            .unwrap_or(0);
+        if from_cpuinfo > 0 {
+            return from_cpuinfo as f32;
+        }
+        let cores = read_file("/sys/devices/system/cpu/online")
+            .ok()
+            .and_then(|online| Self::parse_cpu_list(&online))
+            .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(0);
+        if cores == 0 {
+            warn!("No cores found, load will not be divided by the core count");
+        }
+        cores.max(1) as f32
+    }
+
+    /// `processor : 0` lines. The `Processor : ARMv7 ...` line of older ARM
+    /// kernels names the model rather than counting a core.
+    pub(crate) fn count_processors(cpuinfo: &str) -> usize {
+        cpuinfo
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .filter(|(key, value)| key.trim() == "processor" && value.trim().parse::<u32>().is_ok())
+            .count()
+    }
+
+    /// The number of CPUs in a list like `0-3,6`, as the kernel writes
+    /// /sys/devices/system/cpu/online
+    pub(crate) fn parse_cpu_list(list: &str) -> Option<usize> {
+        let mut count = 0;
+        for range in list.trim().split(',') {
+            count += match range.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+                    last.checked_sub(first)? + 1
+                }
+                None => range.parse::<usize>().map(|_| 1).ok()?,
+            };
+        }
+        (count > 0).then_some(count)
+    }
+
+    pub(crate) fn load(load_avg: [f32; 3], cores: f32) -> [f32; 3] {
+        load_avg.map(|load| load * 100f32 / cores)
+    }
+
+    /// Bytes read and written by a block device since boot
+    pub fn disk_io(device: &str) -> Result<(u64, u64), CollectError> {
+        let path = "/proc/diskstats";
+        Self::parse_diskstats(&read_file(path)?, device)
+            .ok_or_else(|| CollectError::Parse(path.to_string()))
+    }
+
+    /// Lines are `major minor name` and then the I/O counters, sectors read
+    /// being the 3rd and sectors written the 7th. The kernel always counts
+    /// sectors as 512 bytes whatever the device's real sector size.
+    /// <https://www.kernel.org/doc/html/latest/admin-guide/iostats.html>
+    fn parse_diskstats(contents: &str, device: &str) -> Option<(u64, u64)> {
+        contents.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(2) != Some(&device) {
+                return None;
+            }
+            let sectors = |i: usize| fields.get(i)?.parse::<u64>().ok();
+            Some((sectors(5)? * 512, sectors(9)? * 512))
+        })
+    }
+
+    /// Block and inode counts for the filesystem `path` is on, one call
+    /// covers both diskfree and inodefree
+    pub fn statvfs(filesystem: &str) -> Result<FsStats, CollectError> {
+        let failed = |err| CollectError::Statvfs(filesystem.to_string(), err);
+        let path = CString::new(filesystem).map_err(|err| failed(io::Error::other(err)))?;
+        // A zeroed statvfs is a valid one, so nothing is read uninitialized
+        // whatever the call does
+        let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(failed(io::Error::last_os_error()));
+        }
+        Ok(FsStats {
+            blocks: stat.f_blocks as u64,
+            blocks_available: stat.f_bavail as u64,
+            fragment_size: stat.f_frsize as u64,
+            files: stat.f_files as u64,
+            files_available: stat.f_favail as u64,
+            flags: stat.f_flag as u64,
+        })
+    }
+
+    pub(crate) fn disk_free(filesystem: &str, stats: &FsStats) -> f64 {
+        // Pseudo filesystems like /proc have no blocks at all
+        if stats.blocks == 0 {
+            warn!("{filesystem} has no blocks, reporting diskfree as 0");
+            return 0f64;
+        }
+        stats.blocks_available as f64 / stats.blocks as f64 * 100f64
+    }
+
+    /// Bytes an unprivileged user can still write. Blocks are counted in
+    /// fragment sized units, which is `f_bsize` everywhere Linux matters.
+    pub(crate) fn disk_free_bytes(stats: &FsStats) -> u64 {
+        stats.blocks_available.saturating_mul(stats.fragment_size)
+    }
+
+    /// Percent of inodes free. Filesystems that allocate inodes as they go
+    /// (btrfs) report none at all, they can't run out so that's 100.
+    pub(crate) fn inode_free(stats: &FsStats) -> f64 {
+        if stats.files == 0 {
+            return 100f64;
+        }
+        stats.files_available as f64 / stats.files as f64 * 100f64
+    }
+
+    /// Whether the filesystem is mounted read-only, which is what ext4 and
+    /// xfs fall back to after an I/O error with `errors=remount-ro`
+    pub(crate) fn read_only(stats: &FsStats) -> bool {
+        stats.flags as libc::c_ulong & libc::ST_RDONLY != 0
+    }
+}
+
+pub(crate) fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn disabled_collectors() {
+        let config = testing::config(&[
+            "--disable",
+            "load,diskfree",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        let metrics = SysInfo::new(&config).metrics();
+        let names: Vec<String> = metrics.named().into_iter().map(|(name, _)| name).collect();
+        assert!(names.contains(&"uptime".to_string()));
+        assert!(names.contains(&"net-rx".to_string()));
+        for disabled in ["load", "load5", "load15", "diskfree"] {
+            assert!(!names.contains(&disabled.to_string()));
+        }
+        assert!(metrics.load.is_none() && metrics.filesystems.is_empty());
+    }
+
+    #[test]
+    fn stale_values() {
+        let proc = TempDir::new("stale");
+        proc.write("uptime", "3600.00 7200.00\n");
+        proc.write("meminfo", "MemTotal: 1000 kB\nMemAvailable: 250 kB\n");
+        let config = testing::config(&[
+            "--proc-root",
+            proc.path().to_str().unwrap(),
+            "--metrics",
+            "uptime,availmem",
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]);
+        crate::set_roots(&config);
+        let mut info = SysInfo::new(&config);
+        let metrics = info.sample();
+        assert_eq!(
+            (metrics.uptime, metrics.avail_mem),
+            (Some(3600.0), Some(25.0))
+        );
+        // A read that fails, and one that reads nonsense, keep what we had
+        fs::remove_file(proc.join("uptime")).unwrap();
+        proc.write("meminfo", "MemTotal: 2000 kB\nMemAvailable: 2000 kB\n");
+        info.refresh();
+        let metrics = info.sample();
+        assert_eq!(metrics.uptime, Some(3600.0));
+        assert_eq!(metrics.avail_mem, Some(100.0));
+        let err = SysInfo::uptime().unwrap_err().to_string();
+        assert!(
+            err.starts_with(&format!("unable to read {}: ", proc.join("uptime"))),
+            "{err}"
+        );
+        proc.write("uptime", "soon\n");
+        info.refresh();
+        assert_eq!(info.sample().uptime, Some(3600.0));
+        let err = SysInfo::uptime().unwrap_err().to_string();
+        assert_eq!(err, "unexpected contents in /proc/uptime");
+        let err = SysInfo::statvfs("/no/such/dir").unwrap_err().to_string();
+        assert!(err.starts_with("unable to statvfs /no/such/dir: "), "{err}");
+    }
+
+    #[test]
+    fn discover_interfaces() {
+        let net = TempDir::new("discover");
+        for (interface, link_type) in [
+            ("eth1", "1\n"),
+            ("lo", "772\n"),
+            ("eth0", "1\n"),
+            ("lo2", "772\n"),
+        ] {
+            net.write(&format!("{interface}/type"), link_type);
+        }
+        let interfaces = SysInfo::discover_interfaces(net.path().to_str().unwrap()).unwrap();
+        assert_eq!(interfaces, ["eth0", "eth1"]);
+    }
+
+    #[test]
+    fn hostnames() {
+        for hostname in [
+            SysInfo::get_hostname().unwrap(),
+            SysInfo::local_hostname().unwrap(),
+        ] {
+            assert!(!hostname.is_empty());
+            assert_eq!(hostname, hostname.trim());
+        }
+        assert_eq!(
+            SysInfo::parse_hostname("web-01\n").as_deref(),
+            Some("web-01")
+        );
+        assert_eq!(
+            SysInfo::parse_hostname(" web-01 \nstray\n").as_deref(),
+            Some("web-01")
+        );
+        assert_eq!(SysInfo::parse_hostname(""), None);
+        assert_eq!(SysInfo::parse_hostname(" \n\n"), None);
+    }
+
+    #[test]
+    fn unresolvable_fqdn() {
+        assert_eq!(SysInfo::fqdn("nonexistent.invalid"), "nonexistent.invalid");
+        assert_eq!(SysInfo::fqdn("bad\0name"), "bad\0name");
+    }
+
+    #[test]
+    fn machine_id() {
+        let dir = TempDir::new("machine-id");
+        let path = dir.write("machine-id", "4b1e6c9f0d2a4e7c8b3f5a6d9e0c1b2a\n");
+        assert_eq!(
+            SysInfo::read_machine_id(&path).as_deref(),
+            Some("4b1e6c9f0d2a4e7c8b3f5a6d9e0c1b2a")
+        );
+        for malformed in [
+            "",
+            "uninitialized\n",
+            "4b1e6c9f\n",
+            "4b1e6c9f0d2a4e7c8b3f5a6d9e0c1b2z\n",
+        ] {
+            dir.write("machine-id", malformed);
+            assert_eq!(SysInfo::read_machine_id(&path), None, "{malformed:?}");
+        }
+        assert_eq!(SysInfo::read_machine_id(&dir.join("missing")), None);
+    }
+
+    #[test]
+    fn interface_names() {
+        let interfaces = SysInfo::interface_names().unwrap();
+        assert!(interfaces.iter().any(|interface| interface == "lo"));
+    }
+
+    #[test]
+    fn read_link() {
+        let dir = TempDir::new("link");
+        let read = || SysInfo::read_link(dir.path().to_str().unwrap()).unwrap();
+        dir.write("operstate", "up\n");
+        dir.write("speed", "1000\n");
+        assert_eq!(read(), (true, Some(1000)));
+        dir.write("operstate", "down\n");
+        dir.write("speed", "-1\n");
+        assert_eq!(read(), (false, None));
+        dir.write("operstate", "unknown\n");
+        dir.write("carrier", "1\n");
+        fs::remove_file(dir.join("speed")).unwrap();
+        assert_eq!(read(), (true, None));
+    }
+
+    #[test]
+    fn deltas() {
+        let previous = [10, 0, 250, 7];
+        let current = [12, 0, 261, 3];
+        assert_eq!(SysInfo::deltas(current, previous), [2, 0, 11, 0]);
+    }
+
+    #[test]
+    fn parse_uptime() {
+        assert_eq!(SysInfo::parse_uptime("3600.47 7012.90\n"), Some(3600.0));
+        assert_eq!(SysInfo::parse_uptime("3600,47 7012,90\n"), Some(3600.0));
+        for malformed in ["", "\n", "up 3600", "-", "inf 1.0", "3600.47.1 1.0"] {
+            assert_eq!(SysInfo::parse_uptime(malformed), None, "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn parse_btime() {
+        let stat = "cpu  4705 356 584 3699 23 23 0 0 0 0\n\
+                    cpu0 1393 280 155 1832 12 7 0 0 0 0\n\
+                    intr 1462898\n\
+                    ctxt 5241184\n\
+                    btime 1728894137\n\
+                    processes 36489\n";
+        assert_eq!(SysInfo::parse_btime(stat), Some(1728894137));
+        assert_eq!(SysInfo::parse_btime("cpu  4705 356 584\n"), None);
+    }
+
+    #[test]
+    fn avail_mem() {
+        // Fields are found by name, whatever order the lines come in
+        let meminfo = MemInfo::parse("MemAvailable: 2000000 kB\nMemTotal: 8000000 kB\n");
+        assert_eq!(SysInfo::avail_mem(&meminfo), 25.0);
+        let meminfo = MemInfo::parse("MemTotal: lots kB\nMemAvailable 2000000 kB\n: 1\n\n");
+        assert_eq!(SysInfo::avail_mem(&meminfo), 0.0);
+        // Before 3.14 there's no MemAvailable
+        let meminfo =
+            MemInfo::parse("MemTotal: 1000 kB\nMemFree: 100 kB\nBuffers: 50 kB\nCached: 100 kB\n");
+        assert_eq!(SysInfo::avail_mem(&meminfo), 25.0);
+    }
+
+    #[test]
+    fn mem_breakdown() {
+        let meminfo = MemInfo::parse(
+            "MemTotal:       16318460 kB\n\
+             MemFree:         1942176 kB\n\
+             MemAvailable:   11393584 kB\n\
+             Buffers:          815860 kB\n\
+             Cached:          8087164 kB\n\
+             SwapCached:         4712 kB\n\
+             Active:          6573736 kB\n\
+             Inactive:        6407348 kB\n\
+             Dirty:              3264 kB\n\
+             Writeback:             0 kB\n\
+             Slab:             967684 kB\n\
+             SReclaimable:     816672 kB\n\
+             SUnreclaim:       151012 kB\n\
+             SwapTotal:       2097148 kB\n\
+             SwapFree:        2056444 kB\n",
+        );
+        assert_eq!(
+            SysInfo::mem_breakdown(&meminfo),
+            [815860 * 1024, 8087164 * 1024, 3264 * 1024, 816672 * 1024]
+        );
+        let percent = SysInfo::mem_breakdown_percent(&meminfo);
+        assert_eq!(percent[1], 8087164.0 / 16318460.0 * 100.0);
+        assert_eq!(
+            SysInfo::mem_breakdown_percent(&MemInfo::parse("")),
+            [0.0; 4]
+        );
+    }
+
+    #[test]
+    fn swap_used() {
+        let meminfo = MemInfo::parse("SwapTotal: 2000000 kB\nSwapFree: 1500000 kB\n");
+        assert_eq!(SysInfo::swap_used(&meminfo), 25.0);
+        // No swap configured
+        let meminfo = MemInfo::parse("SwapTotal: 0 kB\nSwapFree: 0 kB\n");
+        assert_eq!(SysInfo::swap_used(&meminfo), 0.0);
+        assert_eq!(SysInfo::swap_used(&MemInfo::parse("")), 0.0);
+        // Racing a swapoff can read more free than total
+        let meminfo = MemInfo::parse("SwapTotal: 1000 kB\nSwapFree: 1200 kB\n");
+        assert_eq!(SysInfo::swap_used(&meminfo), 0.0);
+    }
+
+    #[test]
+    fn hottest() {
+        let dir = TempDir::new("thermal");
+        let sensors: Vec<String> = [(0, "41000\n"), (1, "57500\n"), (2, "garbage")]
+            .iter()
+            .map(|(zone, temp)| dir.write(&format!("zone{zone}"), temp))
+            .collect();
+        assert_eq!(SysInfo::hottest(&sensors), Some(57.5));
+        assert_eq!(SysInfo::hottest(&sensors[2..]), None);
+    }
+
+    #[test]
+    fn drive_temps() {
+        let hwmon = TempDir::new("hwmon");
+        for (chip, name, temps) in [
+            ("hwmon0", "coretemp\n", &["52000\n"][..]),
+            ("hwmon1", "nvme\n", &["38850\n", "44850\n", "41850\n"][..]),
+            ("hwmon2", "drivetemp\n", &["31000\n"][..]),
+            ("hwmon3", "nvme\n", &["garbage"][..]),
+        ] {
+            hwmon.write(&format!("{chip}/name"), name);
+            for (n, temp) in temps.iter().enumerate() {
+                hwmon.write(&format!("{chip}/temp{}_input", n + 1), temp);
+            }
+        }
+        std::os::unix::fs::symlink("../../nvme/nvme0", hwmon.join("hwmon1/device")).unwrap();
+        hwmon.mkdir("hwmon2/device/block/sda");
+        let temps = SysInfo::drive_temps(hwmon.path().to_str().unwrap());
+        assert_eq!(
+            temps,
+            [("nvme0".to_string(), 44.85), ("sda".to_string(), 31.0)]
+        );
+        assert!(SysInfo::drive_temps("/no/such/hwmon").is_empty());
+    }
+
+    #[test]
+    fn count_zombies() {
+        let proc = TempDir::new("zombies");
+        for (pid, stat) in [
+            ("1", "1 (systemd) S 0 1 1 0 -1 4194560\n"),
+            ("812", "812 (worker) Z 810 810 810 0 -1 4227148\n"),
+            ("813", "813 (a) b (c)) Z 810 810 810 0 -1 4227148\n"),
+            ("900", "900 (sh) R 1 900 900 34816 900 4194304\n"),
+        ] {
+            proc.write(&format!("{pid}/stat"), stat);
+        }
+        // Gone before its stat was read, and entries that aren't processes
+        proc.mkdir("901");
+        proc.mkdir("sys");
+        proc.write("uptime", "3600.47 7012.90\n");
+        assert_eq!(
+            SysInfo::count_zombies(proc.path().to_str().unwrap()).unwrap(),
+            2
+        );
+        assert!(SysInfo::count_zombies("/no/such/proc").is_err());
+    }
+
+    #[test]
+    fn kernel_release() {
+        let version = "Linux version 6.1.0-18-amd64 (debian-kernel@lists.debian.org) \
+            (gcc-12 (Debian 12.2.0-14) 12.2.0, GNU ld (GNU Binutils for Debian) 2.40) \
+            #1 SMP PREEMPT_DYNAMIC Debian 6.1.76-1 (2024-02-01)\n";
+        assert_eq!(
+            SysInfo::parse_kernel_release(version).as_deref(),
+            Some("6.1.0-18-amd64")
+        );
+        assert_eq!(SysInfo::parse_kernel_release(""), None);
+        assert_eq!(SysInfo::parse_kernel_release("FreeBSD 14.0-RELEASE"), None);
+    }
+
+    #[test]
+    fn kernel_version_number() {
+        assert_eq!(
+            SysInfo::kernel_version_number("6.1.0-18-amd64"),
+            Some(6001000)
+        );
+        assert_eq!(SysInfo::kernel_version_number("4.19.316"), Some(4019316));
+        assert_eq!(SysInfo::kernel_version_number("6.8-rc1"), Some(6008000));
+        assert_eq!(SysInfo::kernel_version_number("5.15.0+"), Some(5015000));
+        for malformed in ["", "6", "six.1.0", "6.1000.0", "6.1.1000"] {
+            assert_eq!(
+                SysInfo::kernel_version_number(malformed),
+                None,
+                "{malformed:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_entropy() {
+        assert_eq!(SysInfo::parse_entropy("3754\n"), Some(3754));
+        assert_eq!(SysInfo::parse_entropy(""), None);
+    }
+
+    #[test]
+    fn conntrack() {
+        let dir = TempDir::new("conntrack");
+        dir.write("nf_conntrack_count", "49152\n");
+        dir.write("nf_conntrack_max", "262144\n");
+        let (count, max) = SysInfo::read_conntrack(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(count, 49152);
+        assert_eq!(SysInfo::conntrack_pct(count, max), 18.75);
+        assert_eq!(SysInfo::conntrack_pct(0, 0), 0.0);
+        fs::remove_file(dir.join("nf_conntrack_max")).unwrap();
+        assert!(SysInfo::read_conntrack(dir.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn psi() {
+        let proc = TempDir::new("pressure");
+        let pressure = |some: &str, full: &str| {
+            format!(
+                "some avg10={some} avg60=0.50 avg300=0.25 total=123456\n\
+                 full avg10={full} avg60=0.00 avg300=0.00 total=0\n"
+            )
+        };
+        proc.write("pressure/cpu", &pressure("1.50", "0.00"));
+        proc.write("pressure/memory", &pressure("0.00", "0.00"));
+        proc.write("pressure/io", &pressure("12.34", "5.00"));
+        crate::set_roots(&testing::config(&[
+            "--proc-root",
+            proc.path().to_str().unwrap(),
+            "localhost",
+            "myapp",
+            "/",
+            "lo",
+        ]));
+        assert_eq!(SysInfo::psi().unwrap(), [1.5, 0.0, 12.34]);
+        assert_eq!(SysInfo::parse_pressure("full avg10=1.00\n"), None);
+        assert_eq!(SysInfo::parse_pressure("some avg60=1.00\n"), None);
+        // Without PSI in the kernel there's no /proc/pressure at all
+        fs::remove_dir_all(proc.join("pressure")).unwrap();
+        assert!(SysInfo::psi().is_err());
+        let config = testing::config(&["--metrics", "psi-cpu", "localhost", "myapp", "/", "lo"]);
+        let mut info = SysInfo::new(&config);
+        assert!(info.sample().psi.is_none());
+        assert!(
+            info.sample()
+                .named()
+                .iter()
+                .all(|(name, _)| name != "psi-cpu")
+        );
+    }
+
+    #[test]
+    fn cores() {
+        let x86 = "processor\t: 0\nmodel name\t: Xeon\n\nprocessor\t: 1\nmodel name\t: Xeon\n";
+        assert_eq!(SysInfo::count_processors(x86), 2);
+        let arm = "Processor\t: ARMv7 Processor rev 10 (v7l)\n\
+                   BogoMIPS\t: 1993.93\n\
+                   Features\t: swp half thumb fastmult vfp edsp neon vfpv3\n\
+                   Hardware\t: Freescale i.MX6 Quad/DualLite\n";
+        assert_eq!(SysInfo::count_processors(arm), 0);
+        assert_eq!(SysInfo::parse_cpu_list("0-3,6,8-9\n"), Some(7));
+        assert_eq!(SysInfo::parse_cpu_list(""), None);
+        let cores = SysInfo::cores();
+        assert!(cores >= 1.0);
+        assert!(
+            SysInfo::load([1.5, 1.0, 0.5], cores)
+                .iter()
+                .all(|load| load.is_finite())
+        );
+        assert_eq!(SysInfo::load([4.0, 2.0, 1.0], 4.0), [100.0, 50.0, 25.0]);
+    }
+
+    #[test]
+    fn parse_diskstats() {
+        let diskstats = "   8       0 sda 100 0 2000 0 50 0 4000 0 0 0 0\n\
+                         \x20  8       1 sda1 90 0 1800 0 40 0 3000 0 0 0 0\n\
+                         \x20  8      16 sdb 1\n";
+        assert_eq!(
+            SysInfo::parse_diskstats(diskstats, "sda"),
+            Some((2000 * 512, 4000 * 512))
+        );
+        assert_eq!(
+            SysInfo::parse_diskstats(diskstats, "sda1"),
+            Some((1800 * 512, 3000 * 512))
+        );
+        // Too few fields, and no such device
+        assert_eq!(SysInfo::parse_diskstats(diskstats, "sdb"), None);
+        assert_eq!(SysInfo::parse_diskstats(diskstats, "sdc"), None);
+    }
+
+    #[test]
+    fn statvfs() {
+        let stats = SysInfo::statvfs("/").unwrap();
+        let free = SysInfo::disk_free("/", &stats);
+        assert!(stats.blocks > 0 && (0.0..=100.0).contains(&free));
+        assert!(SysInfo::disk_free_bytes(&stats) <= stats.blocks * stats.fragment_size);
+        let err = SysInfo::statvfs("/no/such/path").unwrap_err();
+        assert!(err.to_string().contains("No such file or directory"));
+    }
+
+    #[test]
+    fn filesystems() {
+        let dir = TempDir::new("filesystems");
+        let var_lib = dir.mkdir("var/lib");
+        let paths = ["/".to_string(), var_lib.clone(), "/no/such/dir".to_string()];
+        let filesystems = SysInfo::filesystems(&paths);
+        // The one that can't be accessed is skipped
+        let suffixes: Vec<&str> = filesystems.iter().map(|f| f.suffix.as_str()).collect();
+        let var_lib = format!(".{}", Filesystem::metric_name(&var_lib));
+        assert_eq!(suffixes, [".root", var_lib.as_str()]);
+        assert!(var_lib.ends_with("-var-lib"), "{var_lib}");
+        assert_eq!(Filesystem::metric_name("/"), "root");
+        assert_eq!(Filesystem::metric_name("/var/lib/"), "var-lib");
+        assert_eq!(Filesystem::metric_name("/mnt/my disk:1"), "mnt-my-disk-1");
+        // A single filesystem keeps the plain name
+        let filesystems = SysInfo::filesystems(&["/".to_string()]);
+        assert_eq!(filesystems[0].suffix, "");
+        // As does the one left when the others are skipped
+        let filesystems = SysInfo::filesystems(&["/".to_string(), "/no/such/dir".to_string()]);
+        assert_eq!(filesystems[0].suffix, "");
+    }
+
+    #[test]
+    fn disk_free() {
+        let mut stats = FsStats {
+            blocks: 1000,
+            blocks_available: 900,
+            fragment_size: 4096,
+            ..FsStats::default()
+        };
+        assert_eq!(SysInfo::disk_free("/", &stats), 90.0);
+        assert_eq!(SysInfo::disk_free_bytes(&stats), 900 * 4096);
+        // /proc and the like, which would otherwise be NaN
+        stats.blocks = 0;
+        assert_eq!(SysInfo::disk_free("/proc", &stats), 0.0);
+        // Nor is an empty /proc/meminfo
+        assert_eq!(SysInfo::avail_mem(&MemInfo::parse("")), 0.0);
+        assert_eq!(
+            SysInfo::mem_breakdown_percent(&MemInfo::parse("")),
+            [0.0; 4]
+        );
+    }
+
+    #[test]
+    fn inode_free() {
+        let mut stats = FsStats {
+            files: 1000,
+            files_available: 250,
+            ..FsStats::default()
+        };
+        assert_eq!(SysInfo::inode_free(&stats), 25.0);
+        stats.files = 0;
+        assert_eq!(SysInfo::inode_free(&stats), 100.0);
+    }
+
+    #[test]
+    fn read_only() {
+        let mut stats = FsStats {
+            flags: libc::ST_NOSUID | libc::ST_NODEV,
+            ..FsStats::default()
+        };
+        assert!(!SysInfo::read_only(&stats));
+        stats.flags |= libc::ST_RDONLY;
+        assert!(SysInfo::read_only(&stats));
+        let stats = SysInfo::statvfs(std::env::temp_dir().to_str().unwrap()).unwrap();
+        assert!(!SysInfo::read_only(&stats));
+    }
+}
@@ -0,0 +1,91 @@
+//! Fixtures shared by the unit tests
+
+use crate::Config;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Parses a command line the way `main` does, but with no `UPTIMED_*`
+/// variables so the tests don't depend on the environment they're run in
+pub(crate) fn config(args: &[&str]) -> Config {
+    try_config(args).unwrap()
+}
+
+pub(crate) fn try_config(args: &[&str]) -> Result<Config, String> {
+    Config::from_sources(&self::args(args), [])
+}
+
+pub(crate) fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Reads /sys from `sys` for the rest of the calling test, by way of
+/// `--sys-root` like the daemon
+pub(crate) fn sys_root(sys: &TempDir) {
+    let root = sys.path().to_str().unwrap();
+    crate::set_roots(&config(&[
+        "--sys-root",
+        root,
+        "localhost",
+        "myapp",
+        "/",
+        "lo",
+    ]));
+}
+
+/// Sets the byte counters of `interface` under a fake /sys
+pub(crate) fn counters(sys: &TempDir, interface: &str, rx: u64, tx: u64) {
+    let statistics = format!("class/net/{interface}/statistics");
+    sys.write(&format!("{statistics}/rx_bytes"), &format!("{rx}\n"));
+    sys.write(&format!("{statistics}/tx_bytes"), &format!("{tx}\n"));
+}
+
+/// A directory of its own for a test, removed with everything in it when
+/// the test ends
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "uptimed-{name}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `file` under the directory, as the `&str` paths the collectors take
+    pub(crate) fn join(&self, file: &str) -> String {
+        self.path.join(file).to_string_lossy().into_owned()
+    }
+
+    /// Writes `contents` to `file`, making the directories on the way
+    pub(crate) fn write(&self, file: &str, contents: &str) -> String {
+        let path = self.path.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    pub(crate) fn mkdir(&self, dir: &str) -> String {
+        let path = self.path.join(dir);
+        fs::create_dir_all(&path).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
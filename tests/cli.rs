@@ -0,0 +1,407 @@
+//! The `uptimed` binary run as a service manager or a script would run it
+
+use std::{
+    net::UdpSocket,
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// The binary with none of the caller's `UPTIMED_*` variables
+fn uptimed(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_uptimed"));
+    command.args(args);
+    for (var, _) in std::env::vars() {
+        if var.starts_with("UPTIMED_") || var == "NOTIFY_SOCKET" || var == "WATCHDOG_USEC" {
+            command.env_remove(var);
+        }
+    }
+    command
+}
+
+/// A StatsD server on a port of its own
+fn statsd() -> (UdpSocket, String) {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let address = server.local_addr().unwrap().to_string();
+    (server, address)
+}
+
+fn recv(server: &UdpSocket) -> String {
+    let mut buf = [0; 65536];
+    let len = server.recv(&mut buf).unwrap();
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// What systemd would be, the `NOTIFY_SOCKET` a `Type=notify` service gets.
+/// With it set the daemon stays in the foreground, so the test can signal
+/// and wait for it.
+struct Supervisor {
+    path: PathBuf,
+    socket: UnixDatagram,
+}
+
+impl Supervisor {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("uptimed-{name}-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Self { path, socket }
+    }
+
+    fn spawn(&self, mut command: Command) -> Child {
+        command
+            .env("NOTIFY_SOCKET", &self.path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+
+    /// The next state the daemon notified
+    fn recv(&self) -> String {
+        let mut buf = [0; 1024];
+        let len = self.socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn signal(child: &Child, signal: libc::c_int) {
+    assert_eq!(unsafe { libc::kill(child.id() as libc::pid_t, signal) }, 0);
+}
+
+#[test]
+fn first_sample_sent_straight_away() {
+    let (server, address) = statsd();
+    let supervisor = Supervisor::new("first-sample");
+    let args = [
+        "--interval",
+        "3600",
+        "--metrics",
+        "uptime",
+        &address,
+        "myapp",
+        "/",
+        "lo",
+    ];
+    let mut child = supervisor.spawn(uptimed(&args));
+    assert!(supervisor.recv().contains("READY=1"));
+    // Well within the hour the second sample would take
+    assert!(recv(&server).starts_with("myapp."));
+    signal(&child, libc::SIGTERM);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn stops_mid_sleep() {
+    for stop in [libc::SIGTERM, libc::SIGINT] {
+        let (server, address) = statsd();
+        let supervisor = Supervisor::new("stop");
+        let args = [
+            "--interval",
+            "3600",
+            "--metrics",
+            "uptime",
+            &address,
+            "myapp",
+            "/",
+            "lo",
+        ];
+        let mut child = supervisor.spawn(uptimed(&args));
+        assert!(supervisor.recv().contains("READY=1"));
+        recv(&server);
+        let asked = Instant::now();
+        signal(&child, stop);
+        assert_eq!(supervisor.recv(), "STOPPING=1");
+        assert!(child.wait().unwrap().success());
+        assert!(asked.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[test]
+fn reloads_on_sighup() {
+    let (server, address) = statsd();
+    let path = std::env::temp_dir().join(format!("uptimed-reload-{}.toml", std::process::id()));
+    let write_config = |namespace: &str, jitter: u64| {
+        let config = format!(
+            "destination = \"{address}\"\n\
+             namespace = \"{namespace}\"\n\
+             filesystem = \"/\"\n\
+             interface = \"lo\"\n\
+             interval = 1\n\
+             jitter = {jitter}\n\
+             metrics = [\"uptime\"]\n"
+        );
+        std::fs::write(&path, config).unwrap();
+    };
+    write_config("before", 0);
+    let supervisor = Supervisor::new("reload");
+    let mut command = uptimed(&["--config", path.to_str().unwrap()]);
+    command.stderr(Stdio::piped());
+    let child = supervisor.spawn(command);
+    assert!(recv(&server).starts_with("before."));
+    write_config("after", 5);
+    signal(&child, libc::SIGHUP);
+    // The reload lands when the sleep it interrupted would have ended
+    let reloaded = (0..5).any(|_| recv(&server).starts_with("after."));
+    signal(&child, libc::SIGTERM);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let _ = std::fs::remove_file(&path);
+    assert!(reloaded);
+    // The jitter only delays the first sample, that one's gone
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--jitter changed, restart uptimed to apply it"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn once() {
+    let output = uptimed(&[
+        "--once",
+        "--metrics",
+        "uptime,availmem,load",
+        "localhost",
+        "myapp",
+        "/",
+        "lo",
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let names: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(names.len(), 3, "{stdout}");
+    for (name, metric) in names.iter().zip(["uptime", "availmem", "load"]) {
+        assert!(name.starts_with("myapp.") && name.ends_with(&format!(".{metric}")));
+    }
+}
+
+#[test]
+fn count() {
+    let (server, address) = statsd();
+    let args = [
+        "--count",
+        "3",
+        "--interval",
+        "1",
+        "--metrics",
+        "uptime",
+        &address,
+        "myapp",
+        "/",
+        "lo",
+    ];
+    let output = uptimed(&args).output().unwrap();
+    assert!(output.status.success());
+    for _ in 0..3 {
+        assert!(recv(&server).starts_with("myapp."));
+    }
+    // It has exited, so anything more would already be waiting
+    server.set_nonblocking(true).unwrap();
+    let mut buf = [0; 1024];
+    assert!(server.recv(&mut buf).is_err());
+}
+
+#[test]
+fn notifies_systemd() {
+    let (_server, address) = statsd();
+    let supervisor = Supervisor::new("watchdog");
+    let args = [
+        "--interval",
+        "1",
+        "--metrics",
+        "uptime",
+        &address,
+        "myapp",
+        "/",
+        "lo",
+    ];
+    let mut command = uptimed(&args);
+    command.env("WATCHDOG_USEC", "30000000");
+    let mut child = supervisor.spawn(command);
+    assert_eq!(supervisor.recv(), "READY=1\nWATCHDOG=1");
+    assert_eq!(supervisor.recv(), "WATCHDOG=1");
+    signal(&child, libc::SIGTERM);
+    assert_eq!(supervisor.recv(), "STOPPING=1");
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn ready_after_first_send() {
+    // The agent isn't listening when the first sample goes out
+    let agent = std::env::temp_dir().join(format!("uptimed-agent-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&agent);
+    let supervisor = Supervisor::new("ready");
+    let args = [
+        "--interval",
+        "1",
+        "--metrics",
+        "uptime",
+        agent.to_str().unwrap(),
+        "myapp",
+        "/",
+        "lo",
+    ];
+    let mut command = uptimed(&args);
+    command.stderr(Stdio::null());
+    let mut child = supervisor.spawn(command);
+    std::thread::sleep(Duration::from_millis(300));
+    let listening = UnixDatagram::bind(&agent).unwrap();
+    listening
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    assert_eq!(supervisor.recv(), "READY=1");
+    let mut buf = [0; 2048];
+    assert!(listening.recv(&mut buf).unwrap() > 0);
+    signal(&child, libc::SIGTERM);
+    assert!(child.wait().unwrap().success());
+    let _ = std::fs::remove_file(&agent);
+}
+
+#[test]
+fn help_and_version() {
+    for args in [&["--help"][..], &["-h"], &["--interval", "10", "--help"]] {
+        let output = uptimed(args).output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage: uptimed "));
+    }
+    let output = uptimed(&["--version"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("uptimed {}\n", env!("CARGO_PKG_VERSION"))
+    );
+    // A value that happens to look like --help is still a value
+    let args = [
+        "--once",
+        "--interval",
+        "--help",
+        "localhost",
+        "myapp",
+        "/",
+        "lo",
+    ];
+    let output = uptimed(&args).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("Invalid interval \"--help\""),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn lists_under_roots() {
+    let root = std::env::temp_dir().join(format!("uptimed-list-{}", std::process::id()));
+    let statistics = root.join("sys/class/net/fake0/statistics");
+    std::fs::create_dir_all(&statistics).unwrap();
+    std::fs::write(statistics.join("rx_bytes"), "1234\n").unwrap();
+    std::fs::write(statistics.join("tx_bytes"), "5678\n").unwrap();
+    std::fs::write(root.join("sys/class/net/fake0/operstate"), "up\n").unwrap();
+    std::fs::create_dir_all(root.join("proc")).unwrap();
+    std::fs::write(
+        root.join("proc/mounts"),
+        "/dev/fake1 /srv/fake ext4 rw 0 0\n",
+    )
+    .unwrap();
+    let sys = root.join("sys");
+    let proc = root.join("proc");
+
+    // No positional arguments, they're only needed to run
+    let output = uptimed(&["--list-interfaces", "--sys-root", sys.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{stdout}");
+    let fields: Vec<&str> = lines[1].split_whitespace().collect();
+    assert_eq!(fields, ["fake0", "up", "1234", "5678"]);
+
+    let output = uptimed(&["--proc-root", proc.to_str().unwrap(), "--list-filesystems"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    let fields: Vec<&str> = stdout.lines().nth(1).unwrap().split_whitespace().collect();
+    assert_eq!(fields, ["/srv/fake", "ext4", "/dev/fake1"]);
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn jitter_waits_in_background() {
+    let (server, address) = statsd();
+    // Picked out of /proc afterwards, the daemon's pid isn't told to anyone
+    let namespace = format!("jitter{}", std::process::id());
+    let args = [
+        "--jitter",
+        "2",
+        "--metrics",
+        "uptime",
+        &address,
+        &namespace,
+        "/",
+        "lo",
+    ];
+    let started = Instant::now();
+    let status = uptimed(&args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(recv(&server).starts_with(&format!("{namespace}.")));
+    for entry in std::fs::read_dir("/proc").unwrap() {
+        let path = entry.unwrap().path();
+        let Ok(cmdline) = std::fs::read(path.join("cmdline")) else {
+            continue;
+        };
+        if cmdline
+            .split(|&b| b == 0)
+            .any(|arg| arg == namespace.as_bytes())
+            && let Some(pid) = path.file_name().and_then(|pid| pid.to_str()?.parse().ok())
+        {
+            assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+        }
+    }
+}
+
+#[test]
+fn stops_during_jitter() {
+    let (_server, address) = statsd();
+    let supervisor = Supervisor::new("jitter");
+    let args = [
+        "--jitter",
+        "3600",
+        "--metrics",
+        "uptime",
+        &address,
+        "myapp",
+        "/",
+        "lo",
+    ];
+    let mut child = supervisor.spawn(uptimed(&args));
+    std::thread::sleep(Duration::from_millis(300));
+    signal(&child, libc::SIGTERM);
+    assert_eq!(supervisor.recv(), "STOPPING=1");
+    assert!(child.wait().unwrap().success());
+}